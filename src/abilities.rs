@@ -0,0 +1,277 @@
+use bevy::ecs::system::EntityCommands;
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::assets::*;
+use crate::events::*;
+use crate::loading::*;
+use crate::navigation::*;
+use crate::target::*;
+use crate::tower::*;
+use crate::tuning::*;
+use crate::wave::*;
+
+//隐身单位被发现后保持可见的时间
+const REVEAL_LINGER:f32 = 1.5;
+
+//敌人能力,在tuning.ron的abilities里按敌人类型配置,可以任意组合
+#[derive(Deserialize,Clone,Debug)]
+pub enum EnemyAbility{
+    //护盾先于生命值扣除,delay秒没被打中后每秒恢复regen
+    Shield{amount:i32,regen:f32,delay:f32},
+    //每interval秒给radius内的其他敌人回血
+    Healer{radius:f32,amount:i32,interval:f32},
+    //被击落时放出count个enemy
+    Splitter{enemy:EnemyType,count:u32},
+    //塔打不到,除非有塔在reveal_radius以内
+    Stealth{reveal_radius:f32},
+    //每interval秒攻击range内最近的塔
+    Shooter{range:f32,damage:i32,interval:f32},
+    //离开路线冲向aggro内最近的塔,到radius以内撞上,自己也撞毁
+    Rammer{radius:f32,aggro:f32,damage:i32},
+}
+
+#[derive(Component)]
+pub struct ShieldRegen{
+    pub rate:f32,
+    pub delay:f32,
+    //距上次被打中的时间
+    pub since_hit:f32,
+    //不足1点的恢复量
+    pub carry:f32,
+}
+
+#[derive(Component)]
+pub struct Healer{
+    pub radius:f32,
+    pub amount:i32,
+    pub timer:Timer,
+}
+
+#[derive(Component)]
+pub struct Splitter{
+    pub enemy:EnemyType,
+    pub count:u32,
+}
+
+#[derive(Component)]
+pub struct Stealth{
+    pub reveal_radius:f32,
+    //剩余的可见时间
+    pub revealed:f32,
+}
+
+impl Stealth {
+    pub fn hidden(&self) -> bool {
+        self.revealed <= 0.
+    }
+}
+
+#[derive(Component)]
+pub struct TowerAttacker{
+    pub range:f32,
+    pub damage:i32,
+    pub timer:Timer,
+}
+
+#[derive(Component)]
+pub struct Rammer{
+    pub radius:f32,
+    pub aggro:f32,
+    pub damage:i32,
+    //正在冲向的塔和它在地面上的位置
+    pub target:Option<(Entity,Vec3)>,
+}
+
+pub struct EnemyAbilityPlugin;
+
+impl Plugin for EnemyAbilityPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .add_system_set(
+            SystemSet::on_update(GameState::Playing)
+            .with_system(shield_regen)
+            .with_system(healer_pulse)
+            .with_system(splitter_split)
+            .with_system(stealth_reveal)
+            .with_system(attacker_shoot)
+            .with_system(rammer_ram)
+        );
+    }
+}
+
+//spawn_enemy生成敌人时直接挂上,第一帧起隐身和护盾就生效
+pub fn insert_abilities(entity:&mut EntityCommands,abilities:&[EnemyAbility]) {
+    for ability in abilities {
+        match *ability {
+            EnemyAbility::Shield { amount, regen, delay } => {
+                entity
+                .insert(Shield::new(amount))
+                .insert(ShieldRegen{ rate: regen, delay, since_hit: delay, carry: 0. });
+            },
+            EnemyAbility::Healer { radius, amount, interval } => {
+                entity.insert(Healer{ radius, amount, timer: Timer::from_seconds(interval, TimerMode::Repeating) });
+            },
+            EnemyAbility::Splitter { enemy, count } => {
+                entity.insert(Splitter{ enemy, count });
+            },
+            EnemyAbility::Stealth { reveal_radius } => {
+                entity.insert(Stealth{ reveal_radius, revealed: 0. });
+            },
+            EnemyAbility::Shooter { range, damage, interval } => {
+                entity.insert(TowerAttacker{ range, damage, timer: Timer::from_seconds(interval, TimerMode::Repeating) });
+            },
+            EnemyAbility::Rammer { radius, aggro, damage } => {
+                entity.insert(Rammer{ radius, aggro, damage, target: None });
+            },
+        }
+    }
+}
+
+fn shield_regen(
+    mut hits:EventReader<EnemyDamaged>,
+    mut shields:Query<(&mut Shield,&mut ShieldRegen),Without<TargetDeath>>,
+    time:Res<Time>
+){
+    for hit in hits.iter() {
+        if let Ok((_,mut regen)) = shields.get_mut(hit.enemy) {
+            regen.since_hit = 0.;
+        }
+    }
+    for (mut shield,mut regen) in shields.iter_mut() {
+        regen.since_hit += time.delta_seconds();
+        if regen.since_hit < regen.delay || shield.value >= shield.max {
+            regen.carry = 0.;
+            continue;
+        }
+        regen.carry += regen.rate * time.delta_seconds();
+        let restored = regen.carry.floor();
+        regen.carry -= restored;
+        shield.value = (shield.value + restored as i32).min(shield.max);
+    }
+}
+
+fn healer_pulse(
+    mut healers:Query<(Entity,&mut Healer,&GlobalTransform),Without<TargetDeath>>,
+    mut targets:Query<(Entity,&mut Health,&GlobalTransform),(With<Target>,Without<TargetDeath>)>,
+    time:Res<Time>
+){
+    for (healer_e,mut healer,healer_transform) in healers.iter_mut() {
+        healer.timer.tick(time.delta());
+        if !healer.timer.just_finished() {
+            continue;
+        }
+        for (e,mut health,transform) in targets.iter_mut() {
+            if e == healer_e || health.value >= health.max {
+                continue;
+            }
+            if transform.translation().distance(healer_transform.translation()) <= healer.radius {
+                health.value = (health.value + healer.amount).min(health.max);
+            }
+        }
+    }
+}
+
+fn splitter_split(
+    mut commands:Commands,
+    mut waves:ResMut<WaveState>,
+    mut spawned:EventWriter<EnemySpawned>,
+    splitters:Query<(&Splitter,&GlobalTransform,&PathFollower),Added<TargetDeath>>,
+    assets:Res<GameAssets>,
+    tuning:Res<Tuning>,
+    nav:Res<NavGrid>
+){
+    for (splitter,transform,follower) in splitters.iter() {
+        for i in 0..splitter.count {
+            //错开一点,不叠在一起
+            let offset = (i as f32 - (splitter.count as f32 - 1.) / 2.) * 0.3;
+            let position = transform.translation() + Vec3::new(offset,0.,offset);
+            let follower = PathFollower{ waypoints: follower.waypoints.clone(), next: follower.next };
+            let (enemy,position) = spawn_enemy(&mut commands, &assets, &tuning, &nav, splitter.enemy, position, follower);
+            spawned.send(EnemySpawned{ enemy, enemy_type: splitter.enemy, position });
+        }
+        waves.remaining += splitter.count;
+    }
+}
+
+fn stealth_reveal(
+    mut stealthed:Query<(&mut Stealth,&GlobalTransform)>,
+    towers:Query<&GlobalTransform,With<Tower>>,
+    time:Res<Time>
+){
+    for (mut stealth,transform) in stealthed.iter_mut() {
+        let position = transform.translation();
+        let spotted = towers.iter().any(|tower| tower.translation().distance(position) <= stealth.reveal_radius);
+        stealth.revealed = if spotted { REVEAL_LINGER } else { (stealth.revealed - time.delta_seconds()).max(0.) };
+    }
+}
+
+fn nearest_tower<'a>(towers:impl Iterator<Item = (Entity,&'a GlobalTransform)>,position:Vec3,range:f32) -> Option<Entity> {
+    towers
+    .map(|(e,transform)| (e,transform.translation().distance(position)))
+    .filter(|(_,distance)| *distance <= range)
+    .min_by(|a,b| a.1.total_cmp(&b.1))
+    .map(|(e,_)| e)
+}
+
+fn attacker_shoot(
+    mut attackers:Query<(Entity,&mut TowerAttacker,&GlobalTransform),Without<TargetDeath>>,
+    mut damaged:EventWriter<TowerDamaged>,
+    towers:Query<(Entity,&GlobalTransform),(With<Tower>,With<Health>)>,
+    time:Res<Time>
+){
+    for (e,mut attacker,transform) in attackers.iter_mut() {
+        attacker.timer.tick(time.delta());
+        if !attacker.timer.just_finished() {
+            continue;
+        }
+        if let Some(tower) = nearest_tower(towers.iter(),transform.translation(),attacker.range) {
+            damaged.send(TowerDamaged{ tower, enemy: e, damage: attacker.damage });
+        }
+    }
+}
+
+fn rammer_ram(
+    mut commands:Commands,
+    mut damaged:EventWriter<TowerDamaged>,
+    mut rammed:EventWriter<EnemyRammed>,
+    mut rammers:Query<(Entity,&mut Rammer,&mut PathFollower,&EnemyType,&GlobalTransform,&Health),(With<Target>,Without<TargetDeath>)>,
+    towers:Query<(Entity,&GlobalTransform),(With<Tower>,Without<Target>)>
+){
+    for (e,mut rammer,mut follower,enemy_type,transform,health) in rammers.iter_mut() {
+        if health.value <= 0 {
+            continue;
+        }
+        let position = transform.translation();
+        //冲向的塔已经没了,去掉绕过去的路点,回到原来的路线
+        if let Some((tower,point)) = rammer.target {
+            if !towers.contains(tower) {
+                rammer.target = None;
+                if follower.waypoints.get(follower.next) == Some(&point) {
+                    let next = follower.next;
+                    follower.waypoints.remove(next);
+                }
+            }
+        }
+        if rammer.target.is_none() {
+            rammer.target = nearest_tower(towers.iter(),position,rammer.aggro)
+            .and_then(|tower| towers.get(tower).ok())
+            .map(|(tower,t)| (tower,t.translation() * Vec3::new(1.,0.,1.)));
+        }
+        let (tower,point) = match rammer.target {
+            Some(target) => target,
+            None => continue
+        };
+        //先绕到塔那里,撞不上就接着走原来的路线;迷宫里重新寻路会换掉路点,所以每帧都检查
+        if follower.waypoints.get(follower.next) != Some(&point) {
+            let next = follower.next.min(follower.waypoints.len());
+            follower.waypoints.insert(next, point);
+        }
+        if ((position - point) * Vec3::new(1.,0.,1.)).length() <= rammer.radius {
+            damaged.send(TowerDamaged{ tower, enemy: e, damage: rammer.damage });
+            //撞毁后和被击落一样坠落,但不算击杀,没有奖励
+            commands.entity(e).insert(TargetDeath);
+            rammed.send(EnemyRammed{ enemy: e, enemy_type: *enemy_type, tower });
+        }
+    }
+}
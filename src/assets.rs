@@ -1,25 +1,126 @@
+use std::path::Path;
+use bevy::asset::Asset;
 use bevy::prelude::*;
+use bevy::utils::HashMap;
+use serde::Deserialize;
 
-#[derive(Resource,Clone)]
+use crate::loading::*;
+use crate::target::*;
+use crate::tower::*;
+
+//资源清单 assets/manifest.ron,按类型把逻辑名映射到路径
+pub const MANIFEST_PATH:&str = "manifest.ron";
+
+#[derive(Deserialize,Default,Debug)]
+#[serde(default)]
+pub struct AssetManifest{
+    pub scenes:HashMap<String,String>,
+    pub meshes:HashMap<String,String>,
+    pub materials:HashMap<String,String>,
+    pub images:HashMap<String,String>,
+    pub audio:HashMap<String,String>,
+    pub fonts:HashMap<String,String>,
+}
+
+#[derive(Clone,Copy,PartialEq,Eq,PartialOrd,Ord,Debug)]
+pub enum AssetKind{
+    Scene,Mesh,Material,Image,Audio,Font
+}
+
+//代码里用到的资源,清单里缺了就不能开始游戏;塔和敌人的逻辑名从各自的类型里取
+pub fn required_assets() -> Vec<(AssetKind,&'static str)> {
+    let mut required = vec![
+        (AssetKind::Scene,"tower_base"),
+        (AssetKind::Mesh,"tower_base"),
+        (AssetKind::Audio,"enemy_hit"),
+        (AssetKind::Audio,"enemy_death"),
+        (AssetKind::Font,"ui"),
+    ];
+    for tower_type in TowerType::ALL {
+        required.push((AssetKind::Scene,tower_type.asset_key()));
+        required.push((AssetKind::Image,tower_type.asset_key()));
+        required.push((AssetKind::Scene,tower_type.bullet_key()));
+        required.push((AssetKind::Audio,tower_type.sfx_key()));
+    }
+    for enemy_type in EnemyType::ALL {
+        let (model,engine) = enemy_type.asset_keys();
+        required.push((AssetKind::Scene,model));
+        required.push((AssetKind::Audio,engine));
+    }
+    required.sort();
+    required.dedup();
+    required
+}
+
+#[derive(Resource,Clone,Default)]
 pub struct GameAssets{
-    pub tower_base:Handle<Scene>,
-    pub tower_base_mesh:Handle<Mesh>,
-    pub tower_base_material:Handle<StandardMaterial>,
-    pub tower_bottom:Handle<Scene>,
-    pub weapon_cannon:Handle<Scene>,
-    pub weapon_cannon_img:Handle<Image>,
-    pub weapon_ballista:Handle<Scene>,
-    pub weapon_ballista_img:Handle<Image>,
-    pub weapon_blaster:Handle<Scene>,
-    pub weapon_blaster_img:Handle<Image>,
-    pub cannon_bullet:Handle<Scene>,
-    pub ballista_bullet:Handle<Scene>,
-    pub blaster_bullet:Handle<Scene>,
-    pub cannon_fire_audio:Handle<AudioSource>,
-    pub blaster_fire_audio:Handle<AudioSource>,
-    pub ballista_fire_audio:Handle<AudioSource>,
-    pub enemy_red:Handle<Scene>,
-    pub enemy_move_audio:Handle<AudioSource>,
+    pub scenes:HashMap<String,Handle<Scene>>,
+    pub meshes:HashMap<String,Handle<Mesh>>,
+    pub materials:HashMap<String,Handle<StandardMaterial>>,
+    pub images:HashMap<String,Handle<Image>>,
+    pub audio:HashMap<String,Handle<AudioSource>>,
+    pub fonts:HashMap<String,Handle<Font>>,
+}
+
+//按逻辑名取句柄;缺的逻辑名加载时就会报错停在错误界面,进了游戏还缺说明漏登记到required_assets
+fn lookup<T:Asset>(map:&HashMap<String,Handle<T>>,kind:AssetKind,key:&str) -> Handle<T> {
+    match map.get(key) {
+        Some(handle) => handle.clone(),
+        None => panic!("{:?} `{}` is not in assets/{}, add it there and to required_assets()",kind,key,MANIFEST_PATH)
+    }
+}
+
+impl GameAssets {
+    pub fn scene(&self,key:&str) -> Handle<Scene> {
+        lookup(&self.scenes, AssetKind::Scene, key)
+    }
+
+    pub fn mesh(&self,key:&str) -> Handle<Mesh> {
+        lookup(&self.meshes, AssetKind::Mesh, key)
+    }
+
+    pub fn material(&self,key:&str) -> Handle<StandardMaterial> {
+        lookup(&self.materials, AssetKind::Material, key)
+    }
+
+    pub fn image(&self,key:&str) -> Handle<Image> {
+        lookup(&self.images, AssetKind::Image, key)
+    }
+
+    pub fn audio(&self,key:&str) -> Handle<AudioSource> {
+        lookup(&self.audio, AssetKind::Audio, key)
+    }
+
+    //加载界面和错误界面也要用字体,清单坏了的时候不能崩,缺了就是空句柄,看日志
+    pub fn font(&self,key:&str) -> Handle<Font> {
+        self.fonts.get(key).cloned().unwrap_or_else(|| {
+            error!("{:?} `{}` is not in assets/{}",AssetKind::Font,key,MANIFEST_PATH);
+            Handle::default()
+        })
+    }
+
+    pub fn contains(&self,kind:AssetKind,key:&str) -> bool {
+        match kind {
+            AssetKind::Scene => self.scenes.contains_key(key),
+            AssetKind::Mesh => self.meshes.contains_key(key),
+            AssetKind::Material => self.materials.contains_key(key),
+            AssetKind::Image => self.images.contains_key(key),
+            AssetKind::Audio => self.audio.contains_key(key),
+            AssetKind::Font => self.fonts.contains_key(key),
+        }
+    }
+
+    //所有要等加载完的资源
+    pub fn handles(&self) -> Vec<HandleUntyped> {
+        let mut handles = vec![];
+        handles.extend(self.scenes.values().map(|h| h.clone_untyped()));
+        handles.extend(self.meshes.values().map(|h| h.clone_untyped()));
+        handles.extend(self.materials.values().map(|h| h.clone_untyped()));
+        handles.extend(self.images.values().map(|h| h.clone_untyped()));
+        handles.extend(self.audio.values().map(|h| h.clone_untyped()));
+        handles.extend(self.fonts.values().map(|h| h.clone_untyped()));
+        handles
+    }
 }
 
 pub struct GameAssetsPlugin;
@@ -31,41 +132,37 @@ impl Plugin for GameAssetsPlugin {
 
 fn load_assets(
     mut commands:Commands,
+    mut errors:ResMut<LoadErrors>,
     asset:Res<AssetServer>
 ){
-    let tower_base = asset.load("models/towerRound_base.glb#Scene0");
-    let tower_base_mesh = asset.load("models/towerRound_base_mesh.glb#Mesh0/Primitive0");
-    let tower_base_material = asset.load("models/towerRound_base_mesh.glb#Material0");
-    let tower_bottom = asset.load("models/towerRound_bottomA.glb#Scene0");
-
-    let cannon_fire_audio = asset.load("audio/Cannon.wav");
-    let blaster_fire_audio = asset.load("audio/Blaster_short.wav");
-    let ballista_fire_audio = asset.load("audio/Bow_Fire_Arrow.wav");
-    
-    let weapon_cannon = asset.load("models/weapon_cannon.glb#Scene0");
-    let weapon_cannon_img = asset.load("images/weapon_cannon.png");
-
-    let weapon_ballista = asset.load("models/weapon_ballista.glb#Scene0");
-    let weapon_ballista_img = asset.load("images/weapon_ballista.png");
-
-    let weapon_blaster = asset.load("models/weapon_blaster.glb#Scene0");
-    let weapon_blaster_img = asset.load("images/weapon_blaster.png");
-
-
-    let cannon_bullet = asset.load("models/cannon_bullet.glb#Scene0");
-    let ballista_bullet = asset.load("models/ballista_bullet.glb#Scene0");
-    let blaster_bullet = asset.load("models/blaster_bullet.glb#Scene0");
-
-    let enemy_red = asset.load("models/enemy_ufoRed.glb#Scene0");
-    let enemy_move_audio = asset.load("audio/spaceEngineLow_000.ogg");
-    commands.insert_resource(GameAssets{
-        tower_base,
-        tower_base_mesh,tower_base_material,tower_bottom,
-        weapon_cannon,weapon_cannon_img,
-        weapon_ballista,weapon_ballista_img,
-        weapon_blaster,weapon_blaster_img,
-        cannon_bullet,ballista_bullet,blaster_bullet,
-        cannon_fire_audio,blaster_fire_audio,ballista_fire_audio,
-        enemy_red,enemy_move_audio
-    });
-}
\ No newline at end of file
+    //清单决定要加载哪些资源,所以在这里同步读完,路径和其他资源一样由AssetServer解析
+    let manifest = futures_lite::future::block_on(asset.asset_io().load_path(Path::new(MANIFEST_PATH)))
+    .map_err(|e| e.to_string())
+    .and_then(|bytes| ron::de::from_bytes::<AssetManifest>(&bytes).map_err(|e| e.to_string()));
+    let manifest = match manifest {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            errors.0.push(format!("assets/{}: {}",MANIFEST_PATH,e));
+            AssetManifest::default()
+        }
+    };
+
+    fn load_all<T:Asset>(asset:&AssetServer,paths:&HashMap<String,String>) -> HashMap<String,Handle<T>> {
+        paths.iter().map(|(key,path)| (key.clone(),asset.load(path.as_str()))).collect()
+    }
+    let assets = GameAssets{
+        scenes:load_all(&asset, &manifest.scenes),
+        meshes:load_all(&asset, &manifest.meshes),
+        materials:load_all(&asset, &manifest.materials),
+        images:load_all(&asset, &manifest.images),
+        audio:load_all(&asset, &manifest.audio),
+        fonts:load_all(&asset, &manifest.fonts),
+    };
+    for (kind,key) in required_assets() {
+        if !assets.contains(kind, key) {
+            errors.0.push(format!("assets/{}: missing {:?} `{}`",MANIFEST_PATH,kind,key));
+        }
+    }
+    info!("{} assets in manifest",assets.handles().len());
+    commands.insert_resource(assets);
+}
@@ -0,0 +1,131 @@
+use bevy::diagnostic::{Diagnostics, EntityCountDiagnosticsPlugin, FrameTimeDiagnosticsPlugin};
+use bevy::prelude::*;
+use rand::random;
+
+use crate::assets::*;
+use crate::bullet::*;
+use crate::loading::*;
+use crate::tower::*;
+
+//压力测试时保持的子弹数量
+const STRESS_BULLETS:usize = 400;
+//每帧最多补多少发
+const STRESS_BURST:usize = 40;
+
+//F9开关子弹压力测试,F10开关对象池,每秒在日志里打印帧时间和实体变化
+#[derive(Resource)]
+pub struct BulletBenchmark{
+    pub enabled:bool,
+    timer:Timer,
+    //上次打印时的累计数
+    last:(u64,u64,u64),
+}
+
+impl Default for BulletBenchmark {
+    fn default() -> Self {
+        BulletBenchmark { enabled: false, timer: Timer::from_seconds(1., TimerMode::Repeating), last: (0,0,0) }
+    }
+}
+
+pub struct BenchmarkPlugin;
+
+impl Plugin for BenchmarkPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<FrameTimeDiagnosticsPlugin>() {
+            app.add_plugin(FrameTimeDiagnosticsPlugin);
+        }
+        if !app.is_plugin_added::<EntityCountDiagnosticsPlugin>() {
+            app.add_plugin(EntityCountDiagnosticsPlugin);
+        }
+        app
+        .init_resource::<BulletBenchmark>()
+        .add_system_set(
+            SystemSet::on_update(GameState::Playing)
+            .with_system(benchmark_toggle)
+            .with_system(benchmark_fire.after(benchmark_toggle))
+        )
+        .add_system(benchmark_report);
+    }
+}
+
+fn benchmark_toggle(
+    mut commands:Commands,
+    mut benchmark:ResMut<BulletBenchmark>,
+    mut pool:ResMut<BulletPool>,
+    keyboard:Res<Input<KeyCode>>
+){
+    if keyboard.just_pressed(KeyCode::F9) {
+        benchmark.enabled = !benchmark.enabled;
+        benchmark.timer.reset();
+        benchmark.last = (pool.spawned,pool.reused,pool.despawned);
+        info!("bullet benchmark {}",if benchmark.enabled {"on"} else {"off"});
+    }
+    if keyboard.just_pressed(KeyCode::F10) {
+        pool.enabled = !pool.enabled;
+        if !pool.enabled {
+            pool.drain(&mut commands);
+        }
+        info!("bullet pool {}",if pool.enabled {"on"} else {"off"});
+    }
+}
+
+//从地图上空随机位置往四周打子弹,补到STRESS_BULLETS发
+fn benchmark_fire(
+    benchmark:Res<BulletBenchmark>,
+    pool:Res<BulletPool>,
+    assets:Res<GameAssets>,
+    time:Res<Time>,
+    mut fire:EventWriter<FireBullet>
+){
+    if !benchmark.enabled {
+        return;
+    }
+    let missing = STRESS_BULLETS.saturating_sub(pool.active).min(STRESS_BURST);
+    let types = [TowerType::Cannon,TowerType::Ballista,TowerType::Blaster];
+    for i in 0..missing {
+        let tower_type = types[i % types.len()];
+        let position = Vec3::new(random::<f32>() * 8. - 4.,1. + random::<f32>(),random::<f32>() * 8. - 4.);
+        let rotation = Quat::from_rotation_y(random::<f32>() * std::f32::consts::TAU);
+        let (scene,bullet) = tower_type.get_bullet(position,rotation * -Vec3::Z,&time,&assets);
+        fire.send(FireBullet{
+            model:tower_type,
+            scene,
+            bullet,
+            transform:Transform{
+                translation:position,
+                rotation,
+                scale:Vec3::splat(0.05)
+            }
+        });
+    }
+}
+
+fn benchmark_report(
+    mut benchmark:ResMut<BulletBenchmark>,
+    pool:Res<BulletPool>,
+    diagnostics:Res<Diagnostics>,
+    time:Res<Time>
+){
+    if !benchmark.enabled {
+        return;
+    }
+    benchmark.timer.tick(time.raw_delta());
+    if !benchmark.timer.just_finished() {
+        return;
+    }
+    let value = |id| diagnostics.get(id).and_then(|d| d.average()).unwrap_or_default();
+    let (spawned,reused,despawned) = benchmark.last;
+    info!(
+        "pool {} | bullets active {} free {} | per second spawned {} reused {} despawned {} | entities {:.0} | frame {:.2}ms ({:.0} fps)",
+        if pool.enabled {"on"} else {"off"},
+        pool.active,
+        pool.free_count(),
+        pool.spawned - spawned,
+        pool.reused - reused,
+        pool.despawned - despawned,
+        value(EntityCountDiagnosticsPlugin::ENTITY_COUNT),
+        value(FrameTimeDiagnosticsPlugin::FRAME_TIME),
+        value(FrameTimeDiagnosticsPlugin::FPS)
+    );
+    benchmark.last = (pool.spawned,pool.reused,pool.despawned);
+}
@@ -0,0 +1,312 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::assets::*;
+use crate::events::*;
+use crate::loading::*;
+use crate::navigation::*;
+use crate::target::*;
+use crate::tuning::*;
+use crate::wave::*;
+
+const BOSS_BAR_WIDTH:f32 = 400.;
+
+//首领的数值在tuning.ron的bosses里
+#[derive(Deserialize,Clone,Debug)]
+pub struct BossTuning{
+    pub name:String,
+    //模型相对普通敌人的缩放
+    pub scale:f32,
+    //漏到终点扣的生命
+    pub leak_lives:u32,
+    //按血量从高到低排列
+    pub phases:Vec<BossPhase>,
+}
+
+//血量比例降到health以下时触发一次
+#[derive(Deserialize,Clone,Debug)]
+pub struct BossPhase{
+    pub health:f32,
+    pub ability:BossAbility,
+}
+
+#[derive(Deserialize,Clone,Debug)]
+pub enum BossAbility{
+    //速度乘以倍数
+    SpeedUp(f32),
+    //在首领身边放出小怪
+    SpawnMinions{enemy:EnemyType,count:u32},
+    //短时间的护盾
+    Shield{amount:i32,duration:f32},
+}
+
+#[derive(Component,Reflect)]
+#[reflect(Component)]
+pub struct Boss{
+    //下一个要触发的阶段
+    pub phase:usize,
+    pub speed_scale:f32,
+}
+
+impl Default for Boss {
+    fn default() -> Self {
+        Boss { phase: 0, speed_scale: 1.0 }
+    }
+}
+
+//阶段给的护盾叠加在敌人自己的护盾上,到时间后只收回amount这部分
+#[derive(Component)]
+pub struct BossShield{
+    pub amount:i32,
+    pub timer:Timer,
+}
+
+#[derive(Component)]
+pub struct BossBar;
+
+#[derive(Component)]
+pub struct BossBarName;
+
+#[derive(Component)]
+pub enum BossBarFill{
+    Health,
+    Shield
+}
+
+pub struct BossPlugin;
+
+impl Plugin for BossPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .register_type::<Boss>()
+        .add_startup_system(create_boss_bar)
+        .add_system_set(
+            SystemSet::on_update(GameState::Playing)
+            .with_system(boss_attach)
+            .with_system(boss_scale)
+            .with_system(boss_gone)
+            .with_system(boss_phases)
+            .with_system(boss_shield_expire)
+            .with_system(update_boss_bar)
+        );
+    }
+}
+
+//出生的敌人如果是首领就挂上Boss,并记为这一波的目标
+fn boss_attach(
+    mut commands:Commands,
+    mut spawned:EventReader<EnemySpawned>,
+    mut waves:ResMut<WaveState>,
+    tuning:Res<Tuning>
+){
+    for event in spawned.iter() {
+        let boss = match tuning.boss(event.enemy_type) {
+            Some(boss) => boss,
+            None => continue
+        };
+        commands.entity(event.enemy).insert(Boss::default()).insert(Name::new(boss.name.clone()));
+        waves.bosses += 1;
+        info!("boss {} arrived!",boss.name);
+    }
+}
+
+fn boss_scale(
+    mut bosses:Query<(&mut Transform,&EnemyType),Added<Boss>>,
+    tuning:Res<Tuning>
+){
+    for (mut transform,enemy_type) in bosses.iter_mut() {
+        if let Some(boss) = tuning.boss(*enemy_type) {
+            transform.scale *= boss.scale;
+        }
+    }
+}
+
+fn boss_gone(
+    mut killed:EventReader<EnemyKilled>,
+    mut leaked:EventReader<EnemyLeaked>,
+    mut rammed:EventReader<EnemyRammed>,
+    mut waves:ResMut<WaveState>,
+    tuning:Res<Tuning>
+){
+    let killed = killed.iter().map(|e| e.enemy_type);
+    let leaked = leaked.iter().map(|e| e.enemy_type);
+    let rammed = rammed.iter().map(|e| e.enemy_type);
+    for enemy_type in killed.chain(leaked).chain(rammed) {
+        if tuning.boss(enemy_type).is_some() {
+            waves.bosses = waves.bosses.saturating_sub(1);
+        }
+    }
+}
+
+fn boss_phases(
+    mut commands:Commands,
+    mut bosses:Query<(Entity,&mut Boss,&mut Target,&Transform,&Health,&EnemyType,&PathFollower,Option<&mut Shield>,Option<&mut BossShield>),Without<TargetDeath>>,
+    mut waves:ResMut<WaveState>,
+    mut spawned:EventWriter<EnemySpawned>,
+    assets:Res<GameAssets>,
+    tuning:Res<Tuning>,
+    nav:Res<NavGrid>
+){
+    for (e,mut boss,mut target,transform,health,enemy_type,follower,mut shield,mut boss_shield) in bosses.iter_mut() {
+        let boss_tuning = match tuning.boss(*enemy_type) {
+            Some(boss_tuning) => boss_tuning,
+            None => continue
+        };
+        if health.max <= 0 {
+            continue;
+        }
+        let fraction = health.value as f32 / health.max as f32;
+        while let Some(phase) = boss_tuning.phases.get(boss.phase) {
+            if fraction > phase.health {
+                break;
+            }
+            boss.phase += 1;
+            info!("boss {} phase {}: {:?}",boss_tuning.name,boss.phase,phase.ability);
+            match &phase.ability {
+                BossAbility::SpeedUp(multiplier) => {
+                    boss.speed_scale *= multiplier;
+                    target.speed *= multiplier;
+                },
+                BossAbility::SpawnMinions { enemy, count } => {
+                    for i in 0..*count {
+                        //围着首领排开
+                        let angle = i as f32 / *count as f32 * std::f32::consts::TAU;
+                        let offset = Vec3::new(angle.cos(),0.,angle.sin()) * 0.4;
+                        let follower = PathFollower{ waypoints: follower.waypoints.clone(), next: follower.next };
+                        let (minion,position) = spawn_enemy(&mut commands, &assets, &tuning, &nav, *enemy, transform.translation + offset, follower);
+                        spawned.send(EnemySpawned{ enemy: minion, enemy_type: *enemy, position });
+                    }
+                    waves.remaining += count;
+                },
+                BossAbility::Shield { amount, duration } => {
+                    match shield.as_mut() {
+                        Some(shield) => {
+                            shield.value += amount;
+                            shield.max += amount;
+                        },
+                        None => {
+                            commands.entity(e).insert(Shield::new(*amount));
+                        }
+                    }
+                    //上一个阶段的护盾还没到时间就一起延长
+                    match boss_shield.as_mut() {
+                        Some(boss_shield) => {
+                            boss_shield.amount += amount;
+                            boss_shield.timer = Timer::from_seconds(*duration, TimerMode::Once);
+                        },
+                        None => {
+                            commands.entity(e).insert(BossShield{ amount: *amount, timer: Timer::from_seconds(*duration, TimerMode::Once) });
+                        }
+                    }
+                },
+            }
+        }
+    }
+}
+
+fn boss_shield_expire(
+    mut commands:Commands,
+    mut shields:Query<(Entity,&mut BossShield,Option<&mut Shield>)>,
+    time:Res<Time>
+){
+    for (e,mut boss_shield,shield) in shields.iter_mut() {
+        boss_shield.timer.tick(time.delta());
+        if !boss_shield.timer.finished() {
+            continue;
+        }
+        commands.entity(e).remove::<BossShield>();
+        if let Some(mut shield) = shield {
+            //只剩敌人自己的护盾,没有的话整个去掉
+            shield.max -= boss_shield.amount;
+            if shield.max <= 0 {
+                commands.entity(e).remove::<Shield>();
+            } else {
+                shield.value = shield.value.min(shield.max);
+            }
+        }
+    }
+}
+
+//屏幕顶部的首领血条
+fn create_boss_bar(
+    mut commands:Commands,
+    assets:Res<GameAssets>
+){
+    commands.spawn(NodeBundle{
+        style:Style{
+            position_type:PositionType::Absolute,
+            position:UiRect{ top:Val::Px(50.), left:Val::Percent(50.), ..default() },
+            margin:UiRect{ left:Val::Px(-BOSS_BAR_WIDTH / 2.), ..default() },
+            size:Size::new(Val::Px(BOSS_BAR_WIDTH),Val::Auto),
+            flex_direction:FlexDirection::Column,
+            align_items:AlignItems::Center,
+            ..default()
+        },
+        visibility:Visibility{ is_visible:false },
+        ..default()
+    })
+    .insert(BossBar)
+    .insert(Name::new("BossBar"))
+    .with_children(|cb|{
+        cb.spawn(TextBundle::from_section(
+            "",
+            TextStyle { font: assets.font("ui"), font_size: 20., color: Color::WHITE }
+        ))
+        .insert(BossBarName);
+        cb.spawn(NodeBundle{
+            style:Style{
+                size:Size::new(Val::Percent(100.),Val::Px(14.)),
+                margin:UiRect::top(Val::Px(4.)),
+                ..default()
+            },
+            background_color:Color::rgba(0.,0.,0.,0.6).into(),
+            ..default()
+        })
+        .with_children(|cb|{
+            cb.spawn(NodeBundle{
+                style:Style{ size:Size::new(Val::Percent(100.),Val::Percent(100.)), ..default() },
+                background_color:Color::rgb(0.85,0.15,0.15).into(),
+                ..default()
+            })
+            .insert(BossBarFill::Health);
+            cb.spawn(NodeBundle{
+                style:Style{ size:Size::new(Val::Percent(0.),Val::Percent(100.)), ..default() },
+                background_color:Color::rgb(0.3,0.6,1.0).into(),
+                ..default()
+            })
+            .insert(BossBarFill::Shield);
+        });
+    });
+}
+
+fn update_boss_bar(
+    mut bars:Query<&mut Visibility,With<BossBar>>,
+    mut names:Query<&mut Text,With<BossBarName>>,
+    mut fills:Query<(&mut Style,&BossBarFill)>,
+    tuning:Res<Tuning>,
+    bosses:Query<(&Health,Option<&Shield>,&EnemyType,&Boss),Without<TargetDeath>>
+){
+    let boss = bosses.iter().next();
+    for mut visibility in bars.iter_mut() {
+        visibility.is_visible = boss.is_some();
+    }
+    let (health,shield,enemy_type,boss) = match boss {
+        Some(boss) => boss,
+        None => return
+    };
+    let name = tuning.boss(*enemy_type).map(|b| b.name.clone()).unwrap_or_default();
+    let phases = tuning.boss(*enemy_type).map(|b| b.phases.len()).unwrap_or_default();
+    for mut text in names.iter_mut() {
+        text.sections[0].value = format!("{}  phase {}/{}",name,boss.phase + 1,phases + 1);
+    }
+    let max = health.max.max(1) as f32;
+    let health_width = (health.value.max(0) as f32 / max * 100.).min(100.);
+    //护盾接在血条后面,超出的部分不画
+    let shield_width = shield.map(|s| s.value.max(0) as f32 / max * 100.).unwrap_or_default().min(100. - health_width);
+    for (mut style,fill) in fills.iter_mut() {
+        style.size.width = match fill {
+            BossBarFill::Health => Val::Percent(health_width),
+            BossBarFill::Shield => Val::Percent(shield_width),
+        };
+    }
+}
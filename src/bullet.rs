@@ -1,7 +1,11 @@
 use std::f32::consts::PI;
 
 use bevy::prelude::*;
+use bevy::utils::HashMap;
+use crate::events::*;
+use crate::loading::*;
 use crate::target::*;
+use crate::tower::*;
 
 #[derive(Component,Reflect,Default)]
 #[reflect(Component)]
@@ -9,7 +13,7 @@ pub struct Lifetime{
     pub timer:Timer
 }
 
-#[derive(Component,Reflect,Default)]
+#[derive(Component,Reflect,Default,Clone)]
 #[reflect(Component)]
 pub struct Bullet{
     pub old:Vec3,
@@ -18,22 +22,118 @@ pub struct Bullet{
     pub friction_scalar:f32,//摩擦力系数
     pub is_missile:bool,
     pub damage:i32,
+    pub damage_type:DamageType,
+    //暴击
+    pub crit:bool,
+    //发射子弹的塔
+    #[reflect(ignore)]
+    pub source:Option<Entity>,
+}
+
+#[derive(Reflect,Clone,Copy,PartialEq,Eq,Debug,Default)]
+pub enum DamageType{
+    //炮弹
+    #[default]
+    Kinetic,
+    //弩箭
+    Piercing,
+    //能量
+    Energy
+}
+
+impl DamageType {
+    pub fn color(&self) -> Color {
+        match self {
+            DamageType::Kinetic => Color::rgb(1.0,0.85,0.4),
+            DamageType::Piercing => Color::rgb(0.9,0.9,0.9),
+            DamageType::Energy => Color::rgb(0.4,0.9,1.0),
+        }
+    }
 }
 
 #[derive(Component,Reflect,Default)]
 #[reflect(Component)]
 pub struct BulletCollector;
 
+//塔开火,由bullet_fire从对象池里取出子弹
+pub struct FireBullet{
+    pub model:TowerType,
+    pub scene:Handle<Scene>,
+    pub bullet:Bullet,
+    pub transform:Transform,
+}
+
+//池里的子弹,不用时隐藏而不是销毁
+#[derive(Component)]
+pub struct PooledBullet{
+    pub model:TowerType,
+    pub active:bool,
+}
+
+//每种子弹模型一个空闲列表
+#[derive(Resource)]
+pub struct BulletPool{
+    //关掉后每次都新建和销毁,用来对比
+    pub enabled:bool,
+    free:HashMap<TowerType,Vec<Entity>>,
+    pub active:usize,
+    //累计新建,复用和销毁的子弹数
+    pub spawned:u64,
+    pub reused:u64,
+    pub despawned:u64,
+}
+
+impl Default for BulletPool {
+    fn default() -> Self {
+        BulletPool { enabled: true, free: HashMap::default(), active: 0, spawned: 0, reused: 0, despawned: 0 }
+    }
+}
+
+impl BulletPool {
+    pub fn free_count(&self) -> usize {
+        self.free.values().map(|free| free.len()).sum()
+    }
+
+    fn release(&mut self,commands:&mut Commands,e:Entity,pooled:&mut PooledBullet,visibility:&mut Visibility) {
+        if !pooled.active {
+            return;
+        }
+        pooled.active = false;
+        self.active = self.active.saturating_sub(1);
+        if self.enabled {
+            visibility.is_visible = false;
+            self.free.entry(pooled.model).or_default().push(e);
+        } else {
+            commands.entity(e).despawn_recursive();
+            self.despawned += 1;
+        }
+    }
+
+    //关掉对象池时清掉空闲的子弹
+    pub fn drain(&mut self,commands:&mut Commands) {
+        for e in self.free.drain().flat_map(|(_,free)| free) {
+            commands.entity(e).despawn_recursive();
+            self.despawned += 1;
+        }
+    }
+}
+
 pub struct BulletPlugin;
 impl Plugin for BulletPlugin {
     fn build(&self, app: &mut App) {
         app
         .register_type::<Bullet>()
         .register_type::<Lifetime>()
+        .add_event::<FireBullet>()
+        .init_resource::<BulletPool>()
         .add_startup_system(bullet_collect)
-        .add_system(bullet_move)
-        .add_system(bullet_destroy)
-        .add_system(bullet_collision);
+        .add_system_set(
+            SystemSet::on_update(GameState::Playing)
+            .with_system(bullet_fire)
+            .with_system(bullet_move)
+            .with_system(bullet_destroy)
+            .with_system(bullet_collision)
+        );
     }
 }
 
@@ -46,18 +146,76 @@ fn bullet_collect(
     .insert(Name::new("BulletCollector"));
 }
 
+fn bullet_fire(
+    mut commands:Commands,
+    mut events:EventReader<FireBullet>,
+    mut pool:ResMut<BulletPool>,
+    mut bullets:Query<(&mut Bullet,&mut Transform,&mut Lifetime,&mut Visibility,&mut PooledBullet)>,
+    collectors:Query<Entity,With<BulletCollector>>
+){
+    let collector = match collectors.get_single() {
+        Ok(collector) => collector,
+        Err(_) => return
+    };
+    for event in events.iter() {
+        let reuse = if pool.enabled {
+            pool.free.get_mut(&event.model).and_then(|free| free.pop())
+        } else {
+            None
+        };
+        pool.active += 1;
+        if let Some(e) = reuse {
+            if let Ok((mut bullet,mut transform,mut lifetime,mut visibility,mut pooled)) = bullets.get_mut(e) {
+                *bullet = event.bullet.clone();
+                *transform = event.transform;
+                lifetime.timer.reset();
+                visibility.is_visible = true;
+                pooled.active = true;
+                pool.reused += 1;
+                continue;
+            }
+        }
+        let e = commands.spawn(SceneBundle{
+            scene:event.scene.clone(),
+            transform:event.transform,
+            ..default()
+        })
+        .insert(Lifetime{timer:Timer::from_seconds(5., TimerMode::Once)})
+        .insert(event.bullet.clone())
+        .insert(PooledBullet{model:event.model,active:true})
+        .insert(Name::new("Bullet"))
+        .id();
+        commands.entity(collector).add_child(e);
+        pool.spawned += 1;
+    }
+}
+
 fn bullet_move(
     // mut commands:Commands,
-    mut bullets:Query<(&mut Transform, &mut Bullet)>,
+    mut bullets:Query<(&mut Transform, &mut Bullet, &PooledBullet)>,
     time:Res<Time>
 ){
-    for (mut transform,mut bullet) in bullets.iter_mut() {
-        let dv = (bullet.new - bullet.old) * bullet.friction_scalar;
-
-        bullet.old = bullet.new;
-        bullet.new += dv;
-        bullet.new.y -= time.delta_seconds() * bullet.gravity_scalar;
+    //位移按帧积分,加速时每帧多积分几步;暂停不改relative_speed,要单独判断
+    if time.is_paused() {
+        return;
+    }
+    let steps = time.relative_speed().round() as u32;
+    if steps == 0 {
+        return;
+    }
+    //重力按缩放后的帧时间平分到每一步
+    let step_delta = time.delta_seconds() / steps as f32;
+    for (mut transform,mut bullet,pooled) in bullets.iter_mut() {
+        if !pooled.active {
+            continue;
+        }
+        for _ in 0..steps {
+            let dv = (bullet.new - bullet.old) * bullet.friction_scalar;
 
+            bullet.old = bullet.new;
+            bullet.new += dv;
+            bullet.new.y -= step_delta * bullet.gravity_scalar;
+        }
 
         let up = Vec3::Y;
         transform.look_at(bullet.new, up);
@@ -67,29 +225,121 @@ fn bullet_move(
 
 fn bullet_destroy(
     mut commands:Commands,
-    mut query:Query<(Entity,&mut Lifetime),With<Bullet>>,
+    mut pool:ResMut<BulletPool>,
+    mut query:Query<(Entity,&mut Lifetime,&mut PooledBullet,&mut Visibility),With<Bullet>>,
     time:Res<Time>
 ){
-    for (e,mut lifetime) in query.iter_mut() {
+    for (e,mut lifetime,mut pooled,mut visibility) in query.iter_mut() {
+        if !pooled.active {
+            continue;
+        }
         lifetime.timer.tick(time.delta());
         if lifetime.timer.finished() {
-            commands.entity(e).despawn_recursive();
+            pool.release(&mut commands, e, &mut pooled, &mut visibility);
         }
     }
 }
 
 fn bullet_collision(
     mut commands:Commands,
-    mut bullets:Query<(Entity,&GlobalTransform,&Bullet),With<Bullet>>,
-    mut targets:Query<(&GlobalTransform,&mut Health),With<Target>>
+    mut pool:ResMut<BulletPool>,
+    //子弹挂在原点的BulletCollector下,Transform就是世界坐标;刚从池里取出的子弹GlobalTransform还是旧的
+    mut bullets:Query<(Entity,&Transform,&Bullet,&mut PooledBullet,&mut Visibility)>,
+    mut targets:Query<(Entity,&GlobalTransform,&mut Health,Option<&mut Shield>),With<Target>>,
+    mut hits:EventWriter<EnemyDamaged>
 ){
-    for (bullet_e,bullet_transform,bullet) in bullets.iter_mut()  {
-        for (target_transform,mut health) in targets.iter_mut() {
-            if bullet_transform.translation().distance(target_transform.translation()) < 0.2 {
-                commands.entity(bullet_e).despawn_recursive();
-                health.value -= bullet.damage;
+    for (bullet_e,bullet_transform,bullet,mut pooled,mut visibility) in bullets.iter_mut()  {
+        if !pooled.active {
+            continue;
+        }
+        for (target_e,target_transform,mut health,shield) in targets.iter_mut() {
+            if bullet_transform.translation.distance(target_transform.translation()) < 0.2 {
+                pool.release(&mut commands, bullet_e, &mut pooled, &mut visibility);
+                let alive = health.value > 0;
+                let damage = match shield {
+                    Some(mut shield) => shield.absorb(bullet.damage),
+                    None => bullet.damage
+                };
+                health.value -= damage;
+                hits.send(EnemyDamaged{
+                    enemy:target_e,
+                    source:bullet.source,
+                    position:target_transform.translation(),
+                    damage,
+                    damage_type:bullet.damage_type,
+                    crit:bullet.crit,
+                    lethal:alive && health.value <= 0
+                });
                 break;
             }
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use super::*;
+
+    const BULLETS:usize = 400;
+    const BURST:usize = 40;
+    const FRAMES:u32 = 3000;
+
+    //固定60帧步长,子弹寿命按帧数走,和机器快慢无关
+    fn fixed_step(mut time:ResMut<Time>) {
+        let last = time.last_update().unwrap_or_else(|| time.startup());
+        time.update_with_instant(last + Duration::from_secs_f32(1. / 60.));
+    }
+
+    fn keep_firing(pool:Res<BulletPool>,mut fire:EventWriter<FireBullet>) {
+        let missing = BULLETS.saturating_sub(pool.active).min(BURST);
+        let types = [TowerType::Cannon,TowerType::Ballista,TowerType::Blaster];
+        for i in 0..missing {
+            fire.send(FireBullet{
+                model:types[i % types.len()],
+                scene:Handle::default(),
+                bullet:Bullet{ new: Vec3::new(0.01,0.02,0.), friction_scalar: 1., ..default() },
+                transform:Transform::default()
+            });
+        }
+    }
+
+    //返回平均每帧耗时和池的统计
+    fn run(pooled:bool) -> (Duration,u64,u64,usize) {
+        let mut app = App::new();
+        app
+        .add_state(GameState::Playing)
+        .insert_resource(Time::default())
+        .insert_resource(BulletPool{ enabled: pooled, ..default() })
+        .add_plugin(BulletPlugin)
+        .add_event::<EnemyDamaged>()
+        .add_system_to_stage(CoreStage::First, fixed_step)
+        .add_system(keep_firing.before(bullet_fire));
+        //先跑满一轮寿命,池里有了空闲子弹再计时
+        for _ in 0..400 {
+            app.update();
+        }
+        let start = Instant::now();
+        for _ in 0..FRAMES {
+            app.update();
+        }
+        let frame = start.elapsed() / FRAMES;
+        let pool = app.world.resource::<BulletPool>();
+        (frame,pool.spawned,pool.reused,app.world.entities().len() as usize)
+    }
+
+    //cargo test --release bullet_pool_benchmark -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bullet_pool_benchmark() {
+        let (pooled_frame,pooled_spawned,pooled_reused,pooled_entities) = run(true);
+        let (plain_frame,plain_spawned,plain_reused,plain_entities) = run(false);
+        println!("pool on : {:?}/frame, spawned {}, reused {}, entities {}",pooled_frame,pooled_spawned,pooled_reused,pooled_entities);
+        println!("pool off: {:?}/frame, spawned {}, reused {}, entities {}",plain_frame,plain_spawned,plain_reused,plain_entities);
+        println!("speedup : {:.2}x",plain_frame.as_secs_f64() / pooled_frame.as_secs_f64());
+
+        //开了池之后新建的子弹不超过同时在场的数量,其余都是复用
+        assert!(pooled_spawned <= (BULLETS + BURST) as u64);
+        assert!(pooled_reused > 0);
+        assert_eq!(plain_reused, 0);
+    }
+}
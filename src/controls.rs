@@ -0,0 +1,253 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_inspector_egui::bevy_egui::{EguiContext, EguiPlugin};
+use bevy_inspector_egui::egui;
+use leafwing_input_manager::plugin::InputManagerSystem;
+use leafwing_input_manager::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::input::*;
+
+//按键设置保存在工作目录下
+pub const CONTROLS_PATH:&str = "controls.ron";
+
+#[derive(Clone,Copy,PartialEq,Eq,Hash,Debug,Serialize,Deserialize)]
+pub enum Binding{
+    Key(KeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButtonType)
+}
+
+impl Binding {
+    pub fn label(&self) -> String {
+        match self {
+            Binding::Key(key) => format!("{:?}",key),
+            Binding::Mouse(button) => format!("Mouse {:?}",button),
+            Binding::Gamepad(button) => format!("Pad {:?}",button),
+        }
+    }
+}
+
+//可以改键的动作,按菜单里的顺序排列;Move和Orbit是摇杆,不能改
+pub const REBINDABLE:[(Action,&str);24] = [
+    (Action::MoveForward,"Move forward"),
+    (Action::MoveBackward,"Move backward"),
+    (Action::MoveLeft,"Move left"),
+    (Action::MoveRight,"Move right"),
+    (Action::RotateLeft,"Rotate left"),
+    (Action::RotateRight,"Rotate right"),
+    (Action::ZoomIn,"Zoom in"),
+    (Action::ZoomOut,"Zoom out"),
+    (Action::Pause,"Pause"),
+    (Action::FastForward,"Speed"),
+    (Action::BuildMode,"Build mode"),
+    (Action::BuildCannon,"Build cannon"),
+    (Action::BuildBallista,"Build ballista"),
+    (Action::BuildBlaster,"Build blaster"),
+    (Action::Cancel,"Cancel"),
+    (Action::Upgrade,"Upgrade tower"),
+    (Action::Sell,"Sell tower"),
+    (Action::CycleTargeting,"Targeting"),
+    (Action::Repair,"Repair tower"),
+    (Action::SelectNext,"Next tower slot"),
+    (Action::SelectPrevious,"Previous tower slot"),
+    (Action::MenuNext,"Next build option"),
+    (Action::MenuPrevious,"Previous build option"),
+    (Action::Confirm,"Confirm"),
+];
+
+#[derive(Resource,Clone,PartialEq,Debug,Serialize,Deserialize)]
+pub struct Controls{
+    pub bindings:HashMap<Action,Vec<Binding>>
+}
+
+impl Default for Controls {
+    fn default() -> Self {
+        use Binding::*;
+        let bindings = [
+            (Action::MoveForward,vec![Key(KeyCode::W)]),
+            (Action::MoveBackward,vec![Key(KeyCode::S)]),
+            (Action::MoveLeft,vec![Key(KeyCode::A)]),
+            (Action::MoveRight,vec![Key(KeyCode::D)]),
+            (Action::RotateLeft,vec![Key(KeyCode::Q)]),
+            (Action::RotateRight,vec![Key(KeyCode::E)]),
+            (Action::ZoomIn,vec![Key(KeyCode::Equals),Gamepad(GamepadButtonType::RightTrigger2)]),
+            (Action::ZoomOut,vec![Key(KeyCode::Minus),Gamepad(GamepadButtonType::LeftTrigger2)]),
+            (Action::Pause,vec![Key(KeyCode::Space),Gamepad(GamepadButtonType::Start)]),
+            (Action::FastForward,vec![Key(KeyCode::F),Gamepad(GamepadButtonType::RightTrigger)]),
+            (Action::BuildMode,vec![Key(KeyCode::G)]),
+            (Action::BuildCannon,vec![Key(KeyCode::Key1)]),
+            (Action::BuildBallista,vec![Key(KeyCode::Key2)]),
+            (Action::BuildBlaster,vec![Key(KeyCode::Key3)]),
+            (Action::Cancel,vec![Key(KeyCode::Escape),Mouse(MouseButton::Right),Gamepad(GamepadButtonType::East)]),
+            (Action::Upgrade,vec![Key(KeyCode::U),Gamepad(GamepadButtonType::North)]),
+            (Action::Sell,vec![Key(KeyCode::X),Gamepad(GamepadButtonType::West)]),
+            (Action::CycleTargeting,vec![Key(KeyCode::T),Gamepad(GamepadButtonType::LeftTrigger)]),
+            (Action::Repair,vec![Key(KeyCode::R),Gamepad(GamepadButtonType::RightThumb)]),
+            (Action::SelectNext,vec![Key(KeyCode::Tab),Gamepad(GamepadButtonType::DPadRight)]),
+            (Action::SelectPrevious,vec![Gamepad(GamepadButtonType::DPadLeft)]),
+            (Action::MenuNext,vec![Key(KeyCode::Down),Gamepad(GamepadButtonType::DPadDown)]),
+            (Action::MenuPrevious,vec![Key(KeyCode::Up),Gamepad(GamepadButtonType::DPadUp)]),
+            (Action::Confirm,vec![Key(KeyCode::Return),Gamepad(GamepadButtonType::South)]),
+        ];
+        Controls { bindings: bindings.into_iter().collect() }
+    }
+}
+
+impl Controls {
+    //文件里没有的动作用默认按键
+    pub fn load() -> Self {
+        let mut controls = Controls::default();
+        match std::fs::read_to_string(CONTROLS_PATH) {
+            Ok(text) => match ron::from_str::<Controls>(&text) {
+                Ok(saved) => controls.bindings.extend(saved.bindings),
+                Err(e) => warn!("invalid {}: {}",CONTROLS_PATH,e)
+            },
+            Err(_) => {}
+        }
+        controls
+    }
+
+    pub fn save(&self) {
+        let result = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+        .map_err(|e| e.to_string())
+        .and_then(|text| std::fs::write(CONTROLS_PATH, text).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            error!("save controls failed: {}",e);
+        }
+    }
+
+    pub fn input_map(&self) -> InputMap<Action> {
+        let mut input_map = InputMap::default();
+        input_map
+        .set_gamepad(Gamepad{id:0})
+        .insert(DualAxis::left_stick(), Action::Move)
+        .insert(DualAxis::right_stick(), Action::Orbit);
+        for (action,bindings) in self.bindings.iter() {
+            for binding in bindings {
+                match *binding {
+                    Binding::Key(key) => input_map.insert(key, *action),
+                    Binding::Mouse(button) => input_map.insert(button, *action),
+                    Binding::Gamepad(button) => input_map.insert(button, *action),
+                };
+            }
+        }
+        input_map
+    }
+}
+
+#[derive(Resource,Default)]
+pub struct ControlsMenu{
+    pub open:bool,
+    //等待玩家按下新按键的动作
+    pub listening:Option<Action>,
+}
+
+pub struct ControlsPlugin;
+
+impl Plugin for ControlsPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.world.contains_resource::<EguiContext>() {
+            app.add_plugin(EguiPlugin);
+        }
+        app
+        .insert_resource(Controls::load())
+        .init_resource::<ControlsMenu>()
+        .add_system(controls_menu)
+        //在动作状态更新之后,游戏系统读取之前取键
+        .add_system_to_stage(CoreStage::PreUpdate, controls_capture.after(InputManagerSystem::Update))
+        .add_system(controls_apply);
+    }
+}
+
+fn controls_menu(
+    mut menu:ResMut<ControlsMenu>,
+    mut controls:ResMut<Controls>,
+    mut egui_context:ResMut<EguiContext>
+){
+    if !menu.open {
+        return;
+    }
+    let mut open = true;
+    let mut listening = menu.listening;
+    let mut edited = controls.clone();
+    egui::Window::new("Controls").open(&mut open).show(egui_context.ctx_mut(), |ui|{
+        egui::Grid::new("bindings").striped(true).show(ui, |ui|{
+            for (action,name) in REBINDABLE {
+                ui.label(name);
+                let bindings = edited.bindings.entry(action).or_default();
+                let labels:Vec<String> = bindings.iter().map(|b| b.label()).collect();
+                ui.label(if labels.is_empty() { "-".to_string() } else { labels.join(", ") });
+                let text = if listening == Some(action) { "Press a key..." } else { "Rebind" };
+                if ui.button(text).clicked() {
+                    listening = Some(action);
+                }
+                if ui.button("Clear").clicked() {
+                    bindings.clear();
+                }
+                ui.end_row();
+            }
+        });
+        ui.label("Esc cancels rebinding and can't be bound");
+        if ui.button("Reset to defaults").clicked() {
+            edited = Controls::default();
+        }
+    });
+    if !open {
+        listening = None;
+    }
+    menu.open = open;
+    menu.listening = listening;
+    if edited != *controls {
+        *controls = edited;
+    }
+}
+
+//改键时取下一个按下的键盘,鼠标或手柄按键
+fn controls_capture(
+    mut menu:ResMut<ControlsMenu>,
+    mut controls:ResMut<Controls>,
+    keyboard:Res<Input<KeyCode>>,
+    mouse:Res<Input<MouseButton>>,
+    gamepad:Res<Input<GamepadButton>>,
+    mut action_states:Query<&mut ActionState<Action>,With<PlayerInput>>
+){
+    let action = match menu.listening {
+        Some(action) => action,
+        None => return
+    };
+    //等待按键时吞掉所有动作,按下的键不会再触发它原来的动作,松开前也不会触发新动作
+    for mut action_state in action_states.iter_mut() {
+        action_state.consume_all();
+    }
+    //Esc保留给取消改键,不能绑定
+    if keyboard.just_pressed(KeyCode::Escape) {
+        menu.listening = None;
+        return;
+    }
+    //左键留给界面和选塔
+    let binding = keyboard.get_just_pressed().next().map(|k| Binding::Key(*k))
+    .or_else(|| mouse.get_just_pressed().find(|b| **b != MouseButton::Left).map(|b| Binding::Mouse(*b)))
+    .or_else(|| gamepad.get_just_pressed().next().map(|b| Binding::Gamepad(b.button_type)));
+    if let Some(binding) = binding {
+        //同一个键只绑定到一个动作
+        for bindings in controls.bindings.values_mut() {
+            bindings.retain(|b| *b != binding);
+        }
+        controls.bindings.entry(action).or_default().insert(0, binding);
+        menu.listening = None;
+    }
+}
+
+fn controls_apply(
+    controls:Res<Controls>,
+    mut input_maps:Query<&mut InputMap<Action>,With<PlayerInput>>
+){
+    if !controls.is_changed() || controls.is_added() {
+        return;
+    }
+    for mut input_map in input_maps.iter_mut() {
+        *input_map = controls.input_map();
+    }
+    controls.save();
+}
@@ -0,0 +1,382 @@
+use bevy::prelude::*;
+use bevy_inspector_egui::bevy_egui::{EguiContext, EguiPlugin};
+use bevy_inspector_egui::egui;
+
+use crate::input::*;
+use crate::level::*;
+use crate::loading::*;
+use crate::target::*;
+
+#[derive(Clone,Copy,PartialEq,Eq,Debug)]
+pub enum EditorTool{
+    MoveSlot,PlaceSlot,DrawPath,SetSpawn,SetGoal
+}
+
+#[derive(Resource)]
+pub struct LevelEditor{
+    pub enabled:bool,
+    pub tool:EditorTool,
+    pub active_path:usize,
+    pub grid:f32,
+    pub save_path:String,
+    //编辑中的关卡副本
+    pub level:Option<Level>,
+    dragging:Option<(usize,Entity)>,
+}
+
+impl Default for LevelEditor {
+    fn default() -> Self {
+        LevelEditor {
+            enabled: false,
+            tool: EditorTool::MoveSlot,
+            active_path: 0,
+            grid: 0.5,
+            save_path: DEFAULT_LEVEL.to_string(),
+            level: None,
+            dragging: None
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct EditorMarker;
+
+pub struct LevelEditorPlugin;
+
+impl Plugin for LevelEditorPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.world.contains_resource::<EguiContext>() {
+            app.add_plugin(EguiPlugin);
+        }
+        app
+        .init_resource::<LevelEditor>()
+        .add_system_set(
+            SystemSet::on_update(GameState::Playing)
+            .with_system(editor_toggle)
+            .with_system(editor_panel.after(editor_toggle))
+            .with_system(editor_tools.after(editor_panel))
+            .with_system(editor_markers)
+        );
+    }
+}
+
+fn editor_toggle(
+    mut commands:Commands,
+    mut editor:ResMut<LevelEditor>,
+    mut levels:ResMut<Assets<Level>>,
+    keyboard:Res<Input<KeyCode>>,
+    current:Res<CurrentLevel>,
+    markers:Query<Entity,With<EditorMarker>>
+){
+    if !keyboard.just_pressed(KeyCode::F2) {
+        return;
+    }
+    editor.enabled = !editor.enabled;
+    editor.dragging = None;
+    if editor.enabled {
+        info!("level editor on");
+        editor.save_path = current.path.clone();
+        editor.level = levels.get(&current.handle).cloned();
+        //重建关卡,去掉已经造好的塔
+        if let (Some(level),Some(asset)) = (editor.level.as_ref(),levels.get_mut(&current.handle)) {
+            *asset = level.clone();
+        }
+    } else {
+        info!("level editor off");
+        editor.level = None;
+        for e in markers.iter() {
+            commands.entity(e).despawn_recursive();
+        }
+    }
+}
+
+fn editor_panel(
+    mut editor:ResMut<LevelEditor>,
+    mut egui_context:ResMut<EguiContext>,
+    mut levels:ResMut<Assets<Level>>,
+    current:Res<CurrentLevel>
+){
+    if !editor.enabled {
+        return;
+    }
+    let editor = &mut *editor;
+    let level = match editor.level.as_mut() {
+        Some(level) => level,
+        None => return
+    };
+    let mut changed = false;
+
+    egui::Window::new("Level Editor").show(egui_context.ctx_mut(), |ui|{
+        ui.horizontal(|ui|{
+            ui.label("Name");
+            changed |= ui.text_edit_singleline(&mut level.name).changed();
+        });
+        changed |= ui.add(egui::DragValue::new(&mut level.starting_gold).prefix("Gold ")).changed();
+        changed |= ui.add(egui::DragValue::new(&mut level.lives).prefix("Lives ").clamp_range(1..=999)).changed();
+
+        ui.separator();
+        ui.label("Tool");
+        ui.radio_value(&mut editor.tool, EditorTool::MoveSlot, "Move / delete slot (right click)");
+        ui.radio_value(&mut editor.tool, EditorTool::PlaceSlot, "Place slot");
+        ui.radio_value(&mut editor.tool, EditorTool::DrawPath, "Draw path (right click undo)");
+        ui.radio_value(&mut editor.tool, EditorTool::SetSpawn, "Set spawn");
+        ui.radio_value(&mut editor.tool, EditorTool::SetGoal, "Set goal");
+        ui.add(egui::DragValue::new(&mut editor.grid).prefix("Grid ").speed(0.05).clamp_range(0.1..=5.0));
+
+        ui.separator();
+        ui.horizontal(|ui|{
+            let last = level.paths.len().saturating_sub(1);
+            ui.add(egui::DragValue::new(&mut editor.active_path).prefix("Path ").clamp_range(0..=last));
+            if ui.button("Add path").clicked() {
+                level.paths.push(PathDesc{ spawn: level.goal * -1., waypoints: vec![] });
+                editor.active_path = level.paths.len() - 1;
+                changed = true;
+            }
+            if level.paths.len() > 1 && ui.button("Remove path").clicked() {
+                let removed = editor.active_path;
+                level.paths.remove(removed);
+                editor.active_path = removed.min(level.paths.len() - 1);
+                //后面的路径序号前移,走被删路径的敌人改走顶替它的那条
+                for group in level.waves.iter_mut().flat_map(|wave| wave.groups.iter_mut()) {
+                    if group.path > removed {
+                        group.path -= 1;
+                    } else if group.path == removed {
+                        group.path = editor.active_path;
+                    }
+                }
+                changed = true;
+            }
+        });
+        if let Some(path) = level.paths.get_mut(editor.active_path) {
+            if ui.button("Clear waypoints").clicked() {
+                path.waypoints.clear();
+                changed = true;
+            }
+        }
+
+        ui.separator();
+        ui.collapsing("Waves", |ui|{
+            let last_path = level.paths.len().saturating_sub(1);
+            let mut remove_wave = None;
+            for (i,wave) in level.waves.iter_mut().enumerate() {
+                ui.push_id(i, |ui|{
+                    ui.horizontal(|ui|{
+                        ui.label(format!("Wave {}",i+1));
+                        changed |= ui.add(egui::DragValue::new(&mut wave.delay).prefix("delay ").speed(0.1).clamp_range(0.0..=120.0)).changed();
+                        if ui.button("Add group").clicked() {
+                            wave.groups.push(WaveGroup{ enemy: EnemyType::Red, count: 5, interval: 1.0, path: 0 });
+                            changed = true;
+                        }
+                        if ui.button("Remove").clicked() {
+                            remove_wave = Some(i);
+                        }
+                    });
+                    let mut remove_group = None;
+                    for (j,group) in wave.groups.iter_mut().enumerate() {
+                        ui.push_id(j, |ui|{
+                            ui.horizontal(|ui|{
+                                egui::ComboBox::from_id_source("enemy")
+                                .selected_text(format!("{:?}",group.enemy))
+                                .show_ui(ui, |ui|{
+                                    for enemy in EnemyType::ALL {
+                                        changed |= ui.selectable_value(&mut group.enemy, enemy, format!("{:?}",enemy)).changed();
+                                    }
+                                });
+                                changed |= ui.add(egui::DragValue::new(&mut group.count).prefix("x").clamp_range(1..=500)).changed();
+                                changed |= ui.add(egui::DragValue::new(&mut group.interval).prefix("every ").suffix("s").speed(0.05).clamp_range(0.1..=30.0)).changed();
+                                changed |= ui.add(egui::DragValue::new(&mut group.path).prefix("path ").clamp_range(0..=last_path)).changed();
+                                if ui.button("x").clicked() {
+                                    remove_group = Some(j);
+                                }
+                            });
+                        });
+                    }
+                    if let Some(j) = remove_group {
+                        wave.groups.remove(j);
+                        changed = true;
+                    }
+                });
+            }
+            if let Some(i) = remove_wave {
+                level.waves.remove(i);
+                changed = true;
+            }
+            if ui.button("Add wave").clicked() {
+                level.waves.push(WaveDesc{
+                    delay: 5.0,
+                    groups: vec![WaveGroup{ enemy: EnemyType::Red, count: 5, interval: 1.0, path: 0 }]
+                });
+                changed = true;
+            }
+        });
+
+        ui.separator();
+        ui.horizontal(|ui|{
+            ui.text_edit_singleline(&mut editor.save_path);
+            if ui.button("Save").clicked() {
+                save_level(level, &editor.save_path);
+            }
+        });
+    });
+
+    if changed {
+        if let Some(asset) = levels.get_mut(&current.handle) {
+            *asset = level.clone();
+        }
+    }
+}
+
+fn save_level(level:&Level,path:&str) {
+    let file = format!("assets/{}",path);
+    match ron::ser::to_string_pretty(level, ron::ser::PrettyConfig::default()) {
+        Ok(text) => match std::fs::write(&file, text) {
+            Ok(_) => info!("level saved to {}",file),
+            Err(err) => error!("can't write {}: {}",file,err)
+        },
+        Err(err) => error!("can't serialize level: {}",err)
+    }
+}
+
+fn editor_tools(
+    mut editor:ResMut<LevelEditor>,
+    mut egui_context:ResMut<EguiContext>,
+    mut levels:ResMut<Assets<Level>>,
+    mut slots:Query<(Entity,&mut Transform),With<TowerSlot>>,
+    current:Res<CurrentLevel>,
+    cursor:Res<GroundCursor>,
+    mouse:Res<Input<MouseButton>>
+){
+    if !editor.enabled || egui_context.ctx_mut().wants_pointer_input() {
+        return;
+    }
+    let editor = &mut *editor;
+    let (level,position) = match (editor.level.as_mut(),cursor.position) {
+        (Some(level),Some(position)) => (level,position),
+        _ => return
+    };
+    let snapped = (position / editor.grid).round() * editor.grid;
+    let point = Vec2::new(snapped.x,snapped.z);
+    let mut changed = false;
+
+    match editor.tool {
+        EditorTool::MoveSlot => {
+            if mouse.just_pressed(MouseButton::Left) || mouse.just_pressed(MouseButton::Right) {
+                let slot = nearest_slot(level, Vec2::new(position.x,position.z));
+                let entity = slots.iter()
+                .filter(|(_,t)| t.translation.distance(position) < 0.75)
+                .min_by(|(_,a),(_,b)| a.translation.distance(position).total_cmp(&b.translation.distance(position)))
+                .map(|(e,_)| e);
+                if let (Some(index),Some(entity)) = (slot,entity) {
+                    if mouse.just_pressed(MouseButton::Left) {
+                        editor.dragging = Some((index,entity));
+                    } else if editor.dragging.is_none() {
+                        //拖动中不删,否则拖着的下标会错位
+                        level.slots.remove(index);
+                        changed = true;
+                    }
+                }
+            }
+            if let Some((index,entity)) = editor.dragging {
+                if let Ok((_,mut transform)) = slots.get_mut(entity) {
+                    transform.translation = snapped;
+                }
+                if mouse.just_released(MouseButton::Left) {
+                    if let Some(slot) = level.slots.get_mut(index) {
+                        slot.position = point;
+                        changed = true;
+                    }
+                    editor.dragging = None;
+                }
+            }
+        },
+        EditorTool::PlaceSlot => {
+            if mouse.just_pressed(MouseButton::Left) {
+                level.slots.push(SlotDesc{ position: point, rotation: 0. });
+                changed = true;
+            }
+        },
+        EditorTool::DrawPath => {
+            if let Some(path) = level.paths.get_mut(editor.active_path) {
+                if mouse.just_pressed(MouseButton::Left) {
+                    path.waypoints.push(point);
+                    changed = true;
+                }
+                if mouse.just_pressed(MouseButton::Right) {
+                    changed = path.waypoints.pop().is_some();
+                }
+            }
+        },
+        EditorTool::SetSpawn => {
+            if let Some(path) = level.paths.get_mut(editor.active_path) {
+                if mouse.just_pressed(MouseButton::Left) {
+                    path.spawn = point;
+                    changed = true;
+                }
+            }
+        },
+        EditorTool::SetGoal => {
+            if mouse.just_pressed(MouseButton::Left) {
+                level.goal = point;
+                changed = true;
+            }
+        },
+    }
+
+    if changed {
+        if let Some(asset) = levels.get_mut(&current.handle) {
+            *asset = level.clone();
+        }
+    }
+}
+
+fn nearest_slot(level:&Level,position:Vec2) -> Option<usize> {
+    level.slots.iter()
+    .enumerate()
+    .filter(|(_,slot)| slot.position.distance(position) < 0.75)
+    .min_by(|(_,a),(_,b)| a.position.distance(position).total_cmp(&b.position.distance(position)))
+    .map(|(i,_)| i)
+}
+
+//路点标记: 出生点红色,路点黄色,终点蓝色
+fn editor_markers(
+    mut commands:Commands,
+    mut meshes:ResMut<Assets<Mesh>>,
+    mut materials:ResMut<Assets<StandardMaterial>>,
+    mut events:EventReader<AssetEvent<Level>>,
+    editor:Res<LevelEditor>,
+    markers:Query<Entity,With<EditorMarker>>
+){
+    if events.iter().count() == 0 || !editor.enabled {
+        return;
+    }
+    let level = match editor.level.as_ref() {
+        Some(level) => level,
+        None => return
+    };
+    for e in markers.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+
+    let mesh = meshes.add(Mesh::from(shape::UVSphere{ radius: 0.1, ..default() }));
+    let spawn_color = materials.add(Color::RED.into());
+    let waypoint_color = materials.add(Color::YELLOW.into());
+    let goal_color = materials.add(Color::BLUE.into());
+
+    let mut points = vec![(level.goal,goal_color)];
+    for path in level.paths.iter() {
+        points.push((path.spawn,spawn_color.clone()));
+        for waypoint in path.waypoints.iter() {
+            points.push((*waypoint,waypoint_color.clone()));
+        }
+    }
+    for (point,material) in points {
+        commands.spawn(PbrBundle{
+            mesh:mesh.clone(),
+            material,
+            transform:Transform::from_xyz(point.x,0.1,point.y),
+            ..default()
+        })
+        .insert(EditorMarker)
+        .insert(Name::new("EditorMarker"));
+    }
+}
@@ -0,0 +1,224 @@
+use bevy::prelude::*;
+
+use crate::bullet::*;
+use crate::target::*;
+use crate::tower::*;
+
+//游戏里的关键时刻,计分,声音,界面和统计都从这里订阅,模组也可以直接读这些事件
+
+//敌人从出生点出发
+pub struct EnemySpawned{
+    pub enemy:Entity,
+    pub enemy_type:EnemyType,
+    pub position:Vec3,
+}
+
+//子弹命中敌人,damage是护盾吸收后实际扣掉的血
+pub struct EnemyDamaged{
+    pub enemy:Entity,
+    //发射子弹的塔
+    pub source:Option<Entity>,
+    pub position:Vec3,
+    pub damage:i32,
+    pub damage_type:DamageType,
+    pub crit:bool,
+    //这一下把敌人打死了
+    pub lethal:bool,
+}
+
+//敌人被击落,之后还会坠落一会才消失
+pub struct EnemyKilled{
+    pub enemy:Entity,
+    pub enemy_type:EnemyType,
+    pub position:Vec3,
+}
+
+//敌人到达终点
+pub struct EnemyLeaked{
+    pub enemy:Entity,
+    pub enemy_type:EnemyType,
+}
+
+//敌人撞塔后自毁,不给金币和分数
+pub struct EnemyRammed{
+    pub enemy:Entity,
+    pub enemy_type:EnemyType,
+    pub tower:Entity,
+}
+
+//site是塔座实体,塔本身是它的子实体
+pub struct TowerBuilt{
+    pub site:Entity,
+    pub tower_type:TowerType,
+    pub position:Vec3,
+    pub cost:u32,
+}
+
+pub struct TowerUpgraded{
+    pub tower:Entity,
+    pub tower_type:TowerType,
+    pub tier:u32,
+    pub cost:u32,
+}
+
+pub struct TowerSold{
+    pub site:Entity,
+    pub tower_type:TowerType,
+    pub position:Vec3,
+    pub refund:u32,
+}
+
+//敌人打中或撞上塔
+pub struct TowerDamaged{
+    pub tower:Entity,
+    pub enemy:Entity,
+    pub damage:i32,
+}
+
+//塔被打掉,塔位会恢复成空的
+pub struct TowerDestroyed{
+    pub site:Entity,
+    pub tower_type:TowerType,
+    pub position:Vec3,
+}
+
+pub struct TowerRepaired{
+    pub tower:Entity,
+    pub tower_type:TowerType,
+    pub cost:u32,
+}
+
+pub struct ProjectileFired{
+    pub tower:Entity,
+    pub tower_type:TowerType,
+    pub position:Vec3,
+    pub crit:bool,
+}
+
+//本局的累计数据
+#[derive(Resource,Reflect,Default)]
+#[reflect(Resource)]
+pub struct SessionStats{
+    pub enemies_spawned:u32,
+    pub enemies_killed:u32,
+    pub enemies_leaked:u32,
+    pub enemies_rammed:u32,
+    pub towers_built:u32,
+    pub towers_sold:u32,
+    pub towers_destroyed:u32,
+    pub shots_fired:u32,
+    pub damage_dealt:i64,
+}
+
+pub struct GameEventsPlugin;
+
+impl Plugin for GameEventsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .register_type::<SessionStats>()
+        .init_resource::<SessionStats>()
+        .add_event::<EnemySpawned>()
+        .add_event::<EnemyDamaged>()
+        .add_event::<EnemyKilled>()
+        .add_event::<EnemyLeaked>()
+        .add_event::<EnemyRammed>()
+        .add_event::<TowerBuilt>()
+        .add_event::<TowerUpgraded>()
+        .add_event::<TowerSold>()
+        .add_event::<TowerDamaged>()
+        .add_event::<TowerDestroyed>()
+        .add_event::<TowerRepaired>()
+        .add_event::<ProjectileFired>()
+        .add_system(session_stats)
+        .add_system(log_game_events);
+    }
+}
+
+fn session_stats(
+    mut stats:ResMut<SessionStats>,
+    mut spawned:EventReader<EnemySpawned>,
+    mut damaged:EventReader<EnemyDamaged>,
+    mut killed:EventReader<EnemyKilled>,
+    mut leaked:EventReader<EnemyLeaked>,
+    mut rammed:EventReader<EnemyRammed>,
+    mut built:EventReader<TowerBuilt>,
+    mut sold:EventReader<TowerSold>,
+    mut destroyed:EventReader<TowerDestroyed>,
+    mut fired:EventReader<ProjectileFired>
+){
+    stats.enemies_spawned += spawned.iter().count() as u32;
+    stats.damage_dealt += damaged.iter().map(|e| e.damage as i64).sum::<i64>();
+    stats.enemies_killed += killed.iter().count() as u32;
+    stats.enemies_leaked += leaked.iter().count() as u32;
+    stats.enemies_rammed += rammed.iter().count() as u32;
+    stats.towers_built += built.iter().count() as u32;
+    stats.towers_sold += sold.iter().count() as u32;
+    stats.towers_destroyed += destroyed.iter().count() as u32;
+    stats.shots_fired += fired.iter().count() as u32;
+}
+
+fn log_game_events(
+    mut spawned:EventReader<EnemySpawned>,
+    mut killed:EventReader<EnemyKilled>,
+    mut leaked:EventReader<EnemyLeaked>,
+    mut built:EventReader<TowerBuilt>,
+    mut upgraded:EventReader<TowerUpgraded>,
+    mut sold:EventReader<TowerSold>,
+    mut destroyed:EventReader<TowerDestroyed>,
+    mut repaired:EventReader<TowerRepaired>
+){
+    for e in spawned.iter() {
+        debug!("{:?} spawned at {}",e.enemy_type,e.position);
+    }
+    for e in killed.iter() {
+        info!("{:?} killed at {}",e.enemy_type,e.position);
+    }
+    for e in leaked.iter() {
+        info!("{:?} leaked",e.enemy_type);
+    }
+    for e in built.iter() {
+        info!("{:?} built at {} for {} gold",e.tower_type,e.position,e.cost);
+    }
+    for e in upgraded.iter() {
+        info!("{:?} upgraded to tier {} for {} gold",e.tower_type,e.tier,e.cost);
+    }
+    for e in sold.iter() {
+        info!("{:?} sold for {} gold",e.tower_type,e.refund);
+    }
+    for e in destroyed.iter() {
+        info!("{:?} destroyed at {}",e.tower_type,e.position);
+    }
+    for e in repaired.iter() {
+        info!("{:?} repaired for {} gold",e.tower_type,e.cost);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_stats_count_events() {
+        let mut app = App::new();
+        app.add_plugin(GameEventsPlugin);
+
+        let enemy = app.world.spawn_empty().id();
+        let site = app.world.spawn_empty().id();
+        app.world.send_event(EnemySpawned{ enemy, enemy_type: EnemyType::Red, position: Vec3::ZERO });
+        app.world.send_event(EnemySpawned{ enemy, enemy_type: EnemyType::Red, position: Vec3::ZERO });
+        app.world.send_event(EnemyDamaged{ enemy, source: None, position: Vec3::ZERO, damage: 3, damage_type: DamageType::Kinetic, crit: false, lethal: false });
+        app.world.send_event(EnemyDamaged{ enemy, source: None, position: Vec3::ZERO, damage: 4, damage_type: DamageType::Kinetic, crit: false, lethal: true });
+        app.world.send_event(EnemyKilled{ enemy, enemy_type: EnemyType::Red, position: Vec3::ZERO });
+        app.world.send_event(EnemyLeaked{ enemy, enemy_type: EnemyType::Red });
+        app.world.send_event(TowerBuilt{ site, tower_type: TowerType::Cannon, position: Vec3::ZERO, cost: 50 });
+        app.update();
+
+        let stats = app.world.resource::<SessionStats>();
+        assert_eq!(stats.enemies_spawned, 2);
+        assert_eq!(stats.damage_dealt, 7);
+        assert_eq!(stats.enemies_killed, 1);
+        assert_eq!(stats.enemies_leaked, 1);
+        assert_eq!(stats.towers_built, 1);
+        assert_eq!(stats.towers_sold, 0);
+    }
+}
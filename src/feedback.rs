@@ -0,0 +1,219 @@
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+
+use crate::assets::*;
+use crate::events::*;
+use crate::settings::*;
+use crate::target::*;
+
+//同时显示的伤害数字上限,用完后复用最早的
+const DAMAGE_NUMBER_POOL:usize = 32;
+const DAMAGE_NUMBER_LIFETIME:f32 = 0.8;
+const DAMAGE_NUMBER_RISE:f32 = 0.8;
+const HIT_FLASH_TIME:f32 = 0.08;
+
+#[derive(Component)]
+pub struct DamageNumber{
+    pub active:bool,
+    pub position:Vec3,
+    pub timer:Timer,
+    pub color:Color,
+}
+
+#[derive(Resource,Default)]
+pub struct DamageNumberPool{
+    pub entities:Vec<Entity>,
+    next:usize,
+}
+
+//受击闪白,结束后换回原来的材质
+#[derive(Component)]
+pub struct HitFlash{
+    pub timer:Timer,
+    originals:Vec<(Entity,Handle<StandardMaterial>)>,
+}
+
+//原材质对应的闪白材质
+#[derive(Resource,Default)]
+pub struct FlashMaterials(HashMap<Handle<StandardMaterial>,Handle<StandardMaterial>>);
+
+pub struct HitFeedbackPlugin;
+
+impl Plugin for HitFeedbackPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_resource::<DamageNumberPool>()
+        .init_resource::<FlashMaterials>()
+        .add_startup_system(create_damage_numbers)
+        .add_system(damage_number_spawn)
+        .add_system(damage_number_update.after(damage_number_spawn))
+        .add_system(hit_flash_start)
+        .add_system(hit_flash_update.after(hit_flash_start));
+    }
+}
+
+fn create_damage_numbers(
+    mut commands:Commands,
+    mut pool:ResMut<DamageNumberPool>,
+    assets:Res<GameAssets>
+){
+    for _ in 0..DAMAGE_NUMBER_POOL {
+        let e = commands.spawn(TextBundle::from_section(
+            "",
+            TextStyle { font: assets.font("ui"), font_size: 18., color: Color::WHITE }
+        ).with_style(Style{
+            position_type:PositionType::Absolute,
+            ..default()
+        }))
+        .insert(Visibility{ is_visible:false })
+        .insert(DamageNumber{
+            active:false,
+            position:Vec3::ZERO,
+            timer:Timer::from_seconds(DAMAGE_NUMBER_LIFETIME, TimerMode::Once),
+            color:Color::WHITE
+        })
+        .insert(Name::new("DamageNumber"))
+        .id();
+        pool.entities.push(e);
+    }
+}
+
+fn damage_number_spawn(
+    mut hits:EventReader<EnemyDamaged>,
+    mut pool:ResMut<DamageNumberPool>,
+    mut numbers:Query<(&mut DamageNumber,&mut Text)>,
+    settings:Res<Settings>
+){
+    if !settings.damage_numbers || pool.entities.is_empty() {
+        hits.clear();
+        return;
+    }
+    for hit in hits.iter() {
+        let e = pool.entities[pool.next];
+        pool.next = (pool.next + 1) % pool.entities.len();
+        if let Ok((mut number,mut text)) = numbers.get_mut(e) {
+            let color = if hit.crit { Color::rgb(1.0,0.25,0.1) } else { hit.damage_type.color() };
+            number.active = true;
+            number.position = hit.position;
+            number.color = color;
+            number.timer.reset();
+            let section = &mut text.sections[0];
+            section.value = if hit.crit { format!("{}!",hit.damage) } else { hit.damage.to_string() };
+            section.style.font_size = if hit.crit { 28. } else { 18. };
+            section.style.color = color;
+        }
+    }
+}
+
+//飘字向上升起并淡出,位置每帧从世界坐标投影到屏幕
+fn damage_number_update(
+    mut numbers:Query<(&mut DamageNumber,&mut Text,&mut Style,&mut Visibility)>,
+    camera:Query<(&Camera,&GlobalTransform),With<Camera3d>>,
+    time:Res<Time>
+){
+    let (camera,camera_transform) = match camera.get_single() {
+        Ok(camera) => camera,
+        Err(_) => return
+    };
+    for (mut number,mut text,mut style,mut visibility) in numbers.iter_mut() {
+        if !number.active {
+            continue;
+        }
+        number.timer.tick(time.delta());
+        if number.timer.finished() {
+            number.active = false;
+            visibility.is_visible = false;
+            continue;
+        }
+        let rise = number.timer.percent() * DAMAGE_NUMBER_RISE;
+        let screen = camera.world_to_viewport(camera_transform, number.position + Vec3::Y * rise);
+        match screen {
+            Some(screen) => {
+                visibility.is_visible = true;
+                style.position = UiRect{ left:Val::Px(screen.x), bottom:Val::Px(screen.y), ..default() };
+                let mut color = number.color;
+                color.set_a(number.timer.percent_left());
+                text.sections[0].style.color = color;
+            },
+            None => visibility.is_visible = false
+        }
+    }
+}
+
+fn hit_flash_start(
+    mut commands:Commands,
+    mut hits:EventReader<EnemyDamaged>,
+    mut flash_materials:ResMut<FlashMaterials>,
+    mut materials:ResMut<Assets<StandardMaterial>>,
+    mut flashing:Query<&mut HitFlash>,
+    mut handles:Query<&mut Handle<StandardMaterial>>,
+    children:Query<&Children>,
+    alive:Query<(),(With<Target>,Without<TargetDeath>)>,
+    settings:Res<Settings>
+){
+    if !settings.hit_flash {
+        hits.clear();
+        return;
+    }
+    //HitFlash要到帧末才插入,同一帧打中同一只的多发子弹只处理一次
+    let mut started = HashSet::new();
+    for hit in hits.iter() {
+        if !started.insert(hit.enemy) {
+            continue;
+        }
+        if let Ok(mut flash) = flashing.get_mut(hit.enemy) {
+            flash.timer.reset();
+            continue;
+        }
+        //坠落中的敌人不闪
+        if !alive.contains(hit.enemy) {
+            continue;
+        }
+        //敌人模型的材质是所有敌人共用的,只换掉这一只的材质句柄
+        let mut originals = vec![];
+        let mut stack = vec![hit.enemy];
+        while let Some(e) = stack.pop() {
+            //已经是闪白材质的不能当成原材质记下来,否则闪完恢复不回去
+            let handle = handles.get_mut(e).ok()
+            .filter(|handle| !flash_materials.0.values().any(|flash| flash == &**handle));
+            if let Some(mut handle) = handle {
+                let original = handle.clone();
+                let flash = flash_materials.0.entry(original.clone()).or_insert_with(||{
+                    let mut material = materials.get(&original).cloned().unwrap_or_default();
+                    material.base_color = Color::WHITE;
+                    material.emissive = Color::WHITE;
+                    materials.add(material)
+                }).clone();
+                *handle = flash;
+                originals.push((e,original));
+            }
+            if let Ok(cs) = children.get(e) {
+                stack.extend(cs.iter().copied());
+            }
+        }
+        commands.entity(hit.enemy).insert(HitFlash{
+            timer:Timer::from_seconds(HIT_FLASH_TIME, TimerMode::Once),
+            originals
+        });
+    }
+}
+
+fn hit_flash_update(
+    mut commands:Commands,
+    mut flashing:Query<(Entity,&mut HitFlash)>,
+    mut handles:Query<&mut Handle<StandardMaterial>>,
+    time:Res<Time>
+){
+    for (e,mut flash) in flashing.iter_mut() {
+        flash.timer.tick(time.delta());
+        if !flash.timer.finished() {
+            continue;
+        }
+        for (child,original) in flash.originals.drain(..) {
+            if let Ok(mut handle) = handles.get_mut(child) {
+                *handle = original;
+            }
+        }
+        commands.entity(e).remove::<HitFlash>();
+    }
+}
@@ -0,0 +1,144 @@
+use bevy::input::mouse::MouseMotion;
+use bevy::prelude::*;
+use bevy::utils::FloatOrd;
+use bevy_mod_picking::*;
+use leafwing_input_manager::prelude::*;
+
+use crate::input::*;
+use crate::level::*;
+use crate::loading::*;
+use crate::ui::*;
+
+//最近一次操作用的是手柄还是鼠标,决定建造菜单怎么高亮;键盘在菜单里切换也算手柄
+#[derive(Resource,Default,Clone,Copy,PartialEq,Eq,Debug)]
+pub enum ActiveInput{
+    #[default]
+    Mouse,
+    Gamepad
+}
+
+//不用鼠标时按顺序切换塔位和塔,在建造菜单里选塔
+pub struct GamepadControlPlugin;
+
+impl Plugin for GamepadControlPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_resource::<ActiveInput>()
+        .add_system_set(
+            SystemSet::on_update(GameState::Playing)
+            .with_system(active_input)
+            .with_system(gamepad_select)
+            .with_system(gamepad_build_menu)
+            .with_system(build_menu_highlight.after(gamepad_build_menu).after(active_input))
+        );
+    }
+}
+
+fn active_input(
+    mut active:ResMut<ActiveInput>,
+    mut motion:EventReader<MouseMotion>,
+    mouse:Res<Input<MouseButton>>,
+    buttons:Res<Input<GamepadButton>>,
+    actions:Query<&ActionState<Action>,With<PlayerInput>>
+){
+    let menu = actions.iter().any(|action| action.just_pressed(Action::MenuNext) || action.just_pressed(Action::MenuPrevious));
+    let next = if menu || buttons.get_just_pressed().next().is_some() {
+        ActiveInput::Gamepad
+    } else if motion.iter().count() > 0 || mouse.get_just_pressed().next().is_some() {
+        ActiveInput::Mouse
+    } else {
+        return
+    };
+    if *active != next {
+        *active = next;
+    }
+}
+
+fn gamepad_select(
+    mut sites:Query<(Entity,&mut Selection,&GlobalTransform),Or<(With<TowerSlot>,With<TowerSite>)>>,
+    mut cameras:Query<&mut RtsCamera>,
+    actions:Query<&ActionState<Action>,With<PlayerInput>>
+){
+    for action in actions.iter() {
+        if action.just_pressed(Action::Cancel) {
+            for (_,mut selection,_) in sites.iter_mut() {
+                if selection.selected() {
+                    selection.set_selected(false);
+                }
+            }
+            continue;
+        }
+        let step:isize = if action.just_pressed(Action::SelectNext) {
+            1
+        } else if action.just_pressed(Action::SelectPrevious) {
+            -1
+        } else {
+            continue
+        };
+
+        //从左到右,从远到近排列
+        let mut order:Vec<(Entity,Vec3)> = sites.iter().map(|(e,_,t)| (e,t.translation())).collect();
+        if order.is_empty() {
+            continue;
+        }
+        order.sort_by_key(|(_,p)| (FloatOrd(p.x),FloatOrd(p.z)));
+        let current = order.iter().position(|(e,_)|{
+            sites.get(*e).map(|(_,selection,_)| selection.selected()).unwrap_or(false)
+        });
+        let next = match current {
+            Some(i) => (i as isize + step).rem_euclid(order.len() as isize) as usize,
+            None if step > 0 => 0,
+            None => order.len() - 1,
+        };
+        let (next_entity,position) = order[next];
+        for (e,mut selection,_) in sites.iter_mut() {
+            let selected = e == next_entity;
+            if selection.selected() != selected {
+                selection.set_selected(selected);
+            }
+        }
+        for mut camera in cameras.iter_mut() {
+            camera.focus = position * Vec3::new(1.,0.,1.);
+        }
+    }
+}
+
+fn gamepad_build_menu(
+    mut choice:ResMut<BuildMenuChoice>,
+    actions:Query<&ActionState<Action>,With<PlayerInput>>,
+    root:Query<(),With<TowerRootUI>>
+){
+    if root.is_empty() {
+        return;
+    }
+    let len = BUILD_MENU.len();
+    for action in actions.iter() {
+        if action.just_pressed(Action::MenuNext) {
+            choice.0 = (choice.0 + 1) % len;
+        }
+        if action.just_pressed(Action::MenuPrevious) {
+            choice.0 = (choice.0 + len - 1) % len;
+        }
+    }
+}
+
+//用手柄时选中的塔按钮正常显示,其它的变暗;用鼠标时按悬停和按下着色
+fn build_menu_highlight(
+    choice:Res<BuildMenuChoice>,
+    active:Res<ActiveInput>,
+    mut buttons:Query<(&TowerType,&Interaction,&mut BackgroundColor),With<Button>>
+){
+    let chosen = BUILD_MENU[choice.0 % BUILD_MENU.len()];
+    for (tower_type,interaction,mut color) in buttons.iter_mut() {
+        let target = match (*active,interaction) {
+            (ActiveInput::Gamepad,_) if *tower_type == chosen => Color::WHITE,
+            (ActiveInput::Gamepad,_) => Color::rgb(0.6,0.6,0.6),
+            (ActiveInput::Mouse,Interaction::Clicked) => Color::rgb(0.75,0.75,0.75),
+            (ActiveInput::Mouse,Interaction::Hovered) => Color::rgb(0.9,0.9,0.9),
+            (ActiveInput::Mouse,Interaction::None) => Color::WHITE,
+        };
+        if color.0 != target {
+            color.0 = target;
+        }
+    }
+}
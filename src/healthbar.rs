@@ -0,0 +1,151 @@
+use bevy::prelude::*;
+use bevy::pbr::NotShadowCaster;
+use bevy::transform::TransformSystem;
+
+use crate::loading::*;
+use crate::target::*;
+use crate::tower::*;
+
+const BAR_WIDTH:f32 = 0.6;
+const BAR_HEIGHT:f32 = 0.07;
+//血条在敌人头顶的高度
+const BAR_OFFSET:f32 = 0.45;
+
+//敌人头顶的血条,受伤后才显示
+#[derive(Component)]
+pub struct HealthBar{
+    pub target:Entity
+}
+
+#[derive(Component,Clone,Copy,PartialEq,Eq)]
+pub enum HealthBarFill{
+    Health,
+    Shield
+}
+
+#[derive(Resource)]
+pub struct HealthBarAssets{
+    pub quad:Handle<Mesh>,
+    pub background:Handle<StandardMaterial>,
+    pub health:Handle<StandardMaterial>,
+    pub shield:Handle<StandardMaterial>,
+}
+
+impl FromWorld for HealthBarAssets {
+    fn from_world(world: &mut World) -> Self {
+        let quad = world.resource_mut::<Assets<Mesh>>().add(Mesh::from(shape::Quad::new(Vec2::ONE)));
+        let mut materials = world.resource_mut::<Assets<StandardMaterial>>();
+        let mut unlit = |color:Color| materials.add(StandardMaterial{
+            base_color:color,
+            unlit:true,
+            alpha_mode:AlphaMode::Blend,
+            ..default()
+        });
+        HealthBarAssets {
+            quad,
+            background: unlit(Color::rgba(0.05,0.05,0.05,0.7)),
+            health: unlit(Color::rgb(0.85,0.15,0.15)),
+            shield: unlit(Color::rgb(0.3,0.6,1.0)),
+        }
+    }
+}
+
+pub struct HealthBarPlugin;
+
+impl Plugin for HealthBarPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_resource::<HealthBarAssets>()
+        .add_system_set(SystemSet::on_update(GameState::Playing).with_system(health_bar_spawn))
+        .add_system_to_stage(
+            CoreStage::PostUpdate,
+            health_bar_update.before(TransformSystem::TransformPropagate)
+        );
+    }
+}
+
+fn health_bar_spawn(
+    mut commands:Commands,
+    bar_assets:Res<HealthBarAssets>,
+    targets:Query<Entity,(Or<(With<Target>,With<Tower>)>,Added<Health>)>
+){
+    for target in targets.iter() {
+        let quad = |material:&Handle<StandardMaterial>,z:f32| PbrBundle{
+            mesh:bar_assets.quad.clone(),
+            material:material.clone(),
+            transform:Transform{
+                translation:Vec3::new(0.,0.,z),
+                scale:Vec3::new(BAR_WIDTH,BAR_HEIGHT,1.),
+                ..default()
+            },
+            ..default()
+        };
+        commands.spawn(SpatialBundle{
+            visibility:Visibility{ is_visible:false },
+            ..default()
+        })
+        .insert(HealthBar{target})
+        .insert(Name::new("HealthBar"))
+        .with_children(|cb|{
+            cb.spawn(quad(&bar_assets.background,0.)).insert(NotShadowCaster);
+            cb.spawn(quad(&bar_assets.health,0.001))
+            .insert(HealthBarFill::Health)
+            .insert(NotShadowCaster);
+            cb.spawn(quad(&bar_assets.shield,0.001))
+            .insert(HealthBarFill::Shield)
+            .insert(NotShadowCaster);
+        });
+    }
+}
+
+//跟随敌人(包括坠落中的)和受损的塔,始终朝向镜头
+fn health_bar_update(
+    mut commands:Commands,
+    mut bars:Query<(Entity,&HealthBar,&Children,&mut Transform,&mut Visibility)>,
+    mut fills:Query<(&HealthBarFill,&mut Transform,&mut Visibility),Without<HealthBar>>,
+    targets:Query<(&Transform,&Health,Option<&Shield>),(With<Target>,Without<HealthBar>,Without<HealthBarFill>)>,
+    towers:Query<(&GlobalTransform,&Health),With<Tower>>,
+    camera:Query<&GlobalTransform,With<Camera3d>>
+){
+    let camera_rotation = match camera.get_single() {
+        Ok(camera) => camera.compute_transform().rotation,
+        Err(_) => return
+    };
+    for (e,bar,children,mut transform,mut visibility) in bars.iter_mut() {
+        //塔挂在塔座下面,用全局坐标
+        let (position,health,shield) = match (targets.get(bar.target),towers.get(bar.target)) {
+            (Ok((transform,health,shield)),_) => (transform.translation,health,shield),
+            (_,Ok((transform,health))) => (transform.translation(),health,None),
+            _ => {
+                commands.entity(e).despawn_recursive();
+                continue;
+            }
+        };
+        let (shield_value,shield_max) = shield.map(|s| (s.value,s.max)).unwrap_or_default();
+        let damaged = health.value < health.max || shield_value < shield_max;
+        if visibility.is_visible != damaged {
+            visibility.is_visible = damaged;
+        }
+        if !damaged {
+            continue;
+        }
+        transform.translation = position + Vec3::Y * BAR_OFFSET;
+        transform.rotation = camera_rotation;
+
+        //生命和护盾按各自上限在同一条上分段,从左往右排
+        let total = (health.max + shield_max).max(1) as f32;
+        let health_width = health.value.max(0) as f32 / total * BAR_WIDTH;
+        let shield_width = shield_value.max(0) as f32 / total * BAR_WIDTH;
+        for child in children.iter() {
+            if let Ok((fill,mut fill_transform,mut fill_visibility)) = fills.get_mut(*child) {
+                let (left,width) = match fill {
+                    HealthBarFill::Health => (0.,health_width),
+                    HealthBarFill::Shield => (health_width,shield_width),
+                };
+                fill_visibility.is_visible = width > 0.;
+                fill_transform.scale.x = width.max(0.0001);
+                fill_transform.translation.x = -BAR_WIDTH * 0.5 + left + width * 0.5;
+            }
+        }
+    }
+}
@@ -1,68 +1,199 @@
 use std::f32::consts::PI;
 
+use bevy::input::mouse::{MouseMotion, MouseScrollUnit, MouseWheel};
 use bevy::prelude::*;
+use bevy_mod_picking::*;
 use leafwing_input_manager::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Actionlike,PartialEq, Eq, Clone, Copy, Hash, Debug)]
+use crate::level::*;
+use crate::loading::*;
+use crate::settings::*;
+
+//鼠标离窗口边缘多少像素时滚动
+const EDGE_MARGIN:f32 = 8.;
+const MIN_PITCH:f32 = 0.2;
+const MAX_PITCH:f32 = 1.4;
+
+#[derive(Actionlike,PartialEq, Eq, Clone, Copy, Hash, Debug, Serialize, Deserialize)]
 pub enum Action {
-    Move,MoveForward,MoveBackward,MoveLeft,MoveRight
+    Move,MoveForward,MoveBackward,MoveLeft,MoveRight,
+    Pause,FastForward,
+    Orbit,RotateLeft,RotateRight,ZoomIn,ZoomOut,
+    BuildMode,BuildCannon,BuildBallista,BuildBlaster,Cancel,
+    Upgrade,Sell,CycleTargeting,Repair,
+    SelectNext,SelectPrevious,MenuNext,MenuPrevious,Confirm
 }
 
 #[derive(Component)]
 pub struct PlayerInput;
 
+//围绕地面焦点的俯视镜头
+#[derive(Component,Reflect,Default)]
+#[reflect(Component)]
+pub struct RtsCamera{
+    pub focus:Vec3,
+    pub yaw:f32,
+    pub pitch:f32,
+    pub distance:f32,
+    //缩放时镜头高度的范围
+    pub min_height:f32,
+    pub max_height:f32,
+}
+
+impl RtsCamera {
+    pub fn looking_at(eye:Vec3,focus:Vec3) -> Self {
+        let offset = eye - focus;
+        let distance = offset.length();
+        RtsCamera{
+            focus,
+            yaw:offset.x.atan2(offset.z),
+            pitch:(offset.y / distance).asin().clamp(MIN_PITCH, MAX_PITCH),
+            distance,
+            min_height:1.,
+            max_height:8.,
+        }
+    }
+
+    pub fn transform(&self) -> Transform {
+        let rotation = Quat::from_euler(EulerRot::YXZ, self.yaw, -self.pitch, 0.);
+        let eye = self.focus + rotation * Vec3::Z * self.distance;
+        Transform::from_translation(eye).looking_at(self.focus, Vec3::Y)
+    }
+}
+
+//鼠标指向的地面位置
+#[derive(Resource,Default)]
+pub struct GroundCursor{
+    pub position:Option<Vec3>,
+    pub entity:Option<Entity>,
+}
+
 pub struct PlayerInputPlugin;
 
 impl Plugin for PlayerInputPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(camera_controller);
+        app
+        .register_type::<RtsCamera>()
+        .init_resource::<GroundCursor>()
+        .add_system_set(
+            SystemSet::on_update(GameState::Playing)
+            .with_system(ground_cursor)
+            .with_system(camera_controller)
+        );
     }
 }
 
+fn ground_cursor(
+    mut cursor:ResMut<GroundCursor>,
+    cameras:Query<&PickingCamera>
+){
+    let hit = cameras.iter().find_map(|camera| camera.intersect_top());
+    cursor.entity = hit.as_ref().map(|(e,_)| *e);
+    //塔位贴着地面,投影到y=0
+    cursor.position = hit.map(|(_,intersection)| intersection.position() * Vec3::new(1.,0.,1.));
+}
+
 fn camera_controller(
     keyboard:Res<Input<KeyCode>>,
-    mut cameras:Query<&mut Transform,With<Camera3d>>,
+    mouse:Res<Input<MouseButton>>,
+    mut wheel:EventReader<MouseWheel>,
+    mut motion:EventReader<MouseMotion>,
+    mut cameras:Query<(&mut Transform,&mut RtsCamera),With<Camera3d>>,
     actions:Query<&ActionState<Action>,With<PlayerInput>>,
+    windows:Res<Windows>,
+    settings:Res<Settings>,
+    levels:Res<Assets<Level>>,
+    current:Res<CurrentLevel>,
     time:Res<Time>
 ){
-    let mut camera = cameras.get_single_mut().expect("can't find a camera!");
-
-    let mut forward = camera.forward();
-    forward.y = 0.;
-    forward = forward.normalize();
-
-    let mut left = camera.left();
-    left.y = 0.;
-    left = left.normalize();
-
+    //暂停和加速时镜头照常移动
+    let delta = time.raw_delta_seconds();
+    let (mut transform,mut camera) = cameras.get_single_mut().expect("can't find a camera!");
+
+    //平移方向只看水平朝向
+    let forward = Quat::from_rotation_y(camera.yaw) * -Vec3::Z;
+    let right = Quat::from_rotation_y(camera.yaw) * Vec3::X;
+    //离得越远移动越快
+    let speed = settings.camera_speed * camera.distance / 5.;
+
+    let mut pan = Vec2::ZERO;
+    let mut zoom = 0.;
+    let mut orbit = Vec2::ZERO;
     for action in actions.iter() {
-        let dir = action.clamped_axis_pair(Action::Move).unwrap().xy();
-        camera.translation += forward * time.delta_seconds() * dir.y;
-        camera.translation -= left * time.delta_seconds() * dir.x;
+        pan += action.clamped_axis_pair(Action::Move).unwrap().xy();
+        let keys = [
+            (Action::MoveForward,Vec2::Y),
+            (Action::MoveBackward,-Vec2::Y),
+            (Action::MoveLeft,-Vec2::X),
+            (Action::MoveRight,Vec2::X)
+        ];
+        for (key,dir) in keys {
+            if action.pressed(key) {
+                pan += dir;
+            }
+        }
+        if action.pressed(Action::RotateLeft) {
+            orbit.x += delta * PI * 0.1;
+        }
+        if action.pressed(Action::RotateRight) {
+            orbit.x -= delta * PI * 0.1;
+        }
+        if let Some(stick) = action.clamped_axis_pair(Action::Orbit) {
+            orbit += stick.xy() * delta * 2.;
+        }
+        if action.pressed(Action::ZoomIn) {
+            zoom -= delta * 4.;
+        }
+        if action.pressed(Action::ZoomOut) {
+            zoom += delta * 4.;
+        }
     }
 
+    //屏幕边缘滚动
+    if settings.edge_scroll {
+        if let Some((cursor,window)) = windows.get_primary().and_then(|w| w.cursor_position().map(|c| (c,w))) {
+            if cursor.x < EDGE_MARGIN { pan.x -= 1.; }
+            if cursor.x > window.width() - EDGE_MARGIN { pan.x += 1.; }
+            if cursor.y < EDGE_MARGIN { pan.y -= 1.; }
+            if cursor.y > window.height() - EDGE_MARGIN { pan.y += 1.; }
+        }
+    }
+    let pan = pan.clamp_length_max(1.) * speed * delta;
+    camera.focus += forward * pan.y + right * pan.x;
+
+    //中键拖动平移,按住Alt时环绕
+    let drag:Vec2 = motion.iter().map(|m| m.delta).sum();
+    if mouse.pressed(MouseButton::Middle) {
+        if keyboard.pressed(KeyCode::LAlt) || keyboard.pressed(KeyCode::RAlt) {
+            orbit += drag * 0.005;
+        } else {
+            let scale = camera.distance * 0.002;
+            camera.focus += right * -drag.x * scale + forward * drag.y * scale;
+        }
+    }
 
-    // if keyboard.pressed(KeyCode::W) {
-    //     camera.translation += forward * time.delta_seconds()  * 1.5;
-    // }
-
-    // if keyboard.pressed(KeyCode::S) {
-    //     camera.translation -= forward * time.delta_seconds()  * 1.5;
-    // }
-
-    // if keyboard.pressed(KeyCode::A) {
-    //     camera.translation += left * time.delta_seconds() * 1.5;
-    // }
-
-    // if keyboard.pressed(KeyCode::D) {
-    //     camera.translation -= left * time.delta_seconds() * 1.5;
-    // }
+    camera.yaw += orbit.x;
+    camera.pitch = (camera.pitch + orbit.y).clamp(MIN_PITCH, MAX_PITCH);
 
-    if keyboard.pressed(KeyCode::Q) {
-        camera.rotate_y(time.delta_seconds() * PI * 0.1);
+    for event in wheel.iter() {
+        //滚轮单位是行或像素
+        zoom -= match event.unit {
+            MouseScrollUnit::Line => event.y * 0.1,
+            MouseScrollUnit::Pixel => event.y * 0.005,
+        };
     }
-
-    if keyboard.pressed(KeyCode::E) {
-        camera.rotate_y(time.delta_seconds() * PI * -0.1);
+    camera.distance *= (1. + zoom).max(0.1);
+    let sin_pitch = camera.pitch.sin();
+    camera.distance = camera.distance.clamp(camera.min_height / sin_pitch, camera.max_height / sin_pitch);
+
+    //焦点限制在地面范围内
+    if let Some(level) = levels.get(&current.handle) {
+        let half_size = level.ground.size * 0.5;
+        camera.focus.x = camera.focus.x.clamp(-half_size, half_size);
+        camera.focus.z = camera.focus.z.clamp(-half_size, half_size);
     }
-}
\ No newline at end of file
+    camera.focus.y = 0.;
+
+    *transform = camera.transform();
+}
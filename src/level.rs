@@ -0,0 +1,395 @@
+use bevy::{prelude::*, pbr::NotShadowCaster};
+use bevy::asset::{AssetLoader, LoadContext, LoadedAsset, BoxedFuture};
+use bevy::reflect::TypeUuid;
+use bevy_mod_picking::*;
+use serde::{Deserialize, Serialize};
+
+use crate::assets::*;
+use crate::loading::*;
+use crate::target::*;
+use crate::ui::*;
+use crate::wave::*;
+use crate::navigation::*;
+
+pub const DEFAULT_LEVEL:&str = "levels/meadow.level.ron";
+
+//关卡文件 assets/levels/*.level.ron
+#[derive(TypeUuid,Serialize,Deserialize,Clone,Debug)]
+#[uuid = "6b0c3f5e-4a8e-4f59-9d43-1f7c2a9e3b10"]
+pub struct Level{
+    pub name:String,
+    pub starting_gold:u32,
+    pub lives:u32,
+    pub ground:GroundDesc,
+    pub lighting:LightingDesc,
+    pub slots:Vec<SlotDesc>,
+    pub paths:Vec<PathDesc>,
+    pub goal:Vec2,
+    pub waves:Vec<WaveDesc>,
+    //迷宫地图: 地面单位用A*寻路,建塔不能堵死路线
+    #[serde(default)]
+    pub maze:bool,
+}
+
+#[derive(Serialize,Deserialize,Clone,Debug)]
+pub struct GroundDesc{
+    pub size:f32,
+    pub color:Color,
+}
+
+#[derive(Serialize,Deserialize,Clone,Debug)]
+pub struct LightingDesc{
+    pub illuminance:f32,
+    pub shadows:bool,
+    //角度制
+    pub pitch:f32,
+    #[serde(default)]
+    pub yaw:f32,
+    pub ambient_color:Color,
+    pub ambient_brightness:f32,
+}
+
+//可建造的塔位,地面坐标(x,z)
+#[derive(Serialize,Deserialize,Clone,Debug)]
+pub struct SlotDesc{
+    pub position:Vec2,
+    #[serde(default)]
+    pub rotation:f32,
+}
+
+//出生点经过waypoints到达终点
+#[derive(Serialize,Deserialize,Clone,Debug)]
+pub struct PathDesc{
+    pub spawn:Vec2,
+    #[serde(default)]
+    pub waypoints:Vec<Vec2>,
+}
+
+#[derive(Serialize,Deserialize,Clone,Debug)]
+pub struct WaveDesc{
+    //上一波出完怪后等待的秒数
+    pub delay:f32,
+    pub groups:Vec<WaveGroup>,
+}
+
+#[derive(Serialize,Deserialize,Clone,Debug)]
+pub struct WaveGroup{
+    pub enemy:EnemyType,
+    pub count:u32,
+    pub interval:f32,
+    #[serde(default)]
+    pub path:usize,
+}
+
+impl Level {
+    //出生点+路点+终点
+    pub fn route(&self,path:usize) -> Vec<Vec3> {
+        let desc = &self.paths[path];
+        std::iter::once(desc.spawn)
+        .chain(desc.waypoints.iter().copied())
+        .chain(std::iter::once(self.goal))
+        .map(|p| Vec3::new(p.x,0.,p.y))
+        .collect()
+    }
+
+    //波次的时间会直接用来建计时器,负数或0会让计时器崩掉
+    pub fn validate(&self) -> Result<(),String> {
+        if self.paths.is_empty() {
+            return Err("level has no paths".to_string());
+        }
+        for (i,wave) in self.waves.iter().enumerate() {
+            if !wave.delay.is_finite() || wave.delay < 0. {
+                return Err(format!("waves[{}].delay must not be negative, got {}",i,wave.delay));
+            }
+            for (j,group) in wave.groups.iter().enumerate() {
+                if !group.interval.is_finite() || group.interval <= 0. {
+                    return Err(format!("waves[{}].groups[{}].interval must be positive, got {}",i,j,group.interval));
+                }
+                if group.path >= self.paths.len() {
+                    return Err(format!("waves[{}].groups[{}].path {} is out of range, the level has {} paths",i,j,group.path,self.paths.len()));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct LevelLoader;
+
+impl AssetLoader for LevelLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let level = ron::de::from_bytes::<Level>(bytes)?;
+            level.validate().map_err(bevy::asset::Error::msg)?;
+            load_context.set_default_asset(LoadedAsset::new(level));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["level.ron"]
+    }
+}
+
+#[derive(Resource)]
+pub struct CurrentLevel{
+    pub path:String,
+    pub handle:Handle<Level>
+}
+
+#[derive(Resource,Reflect,Default)]
+#[reflect(Resource)]
+pub struct PlayerStats{
+    pub gold:u32,
+    pub lives:u32,
+    pub score:u32,
+}
+
+//关卡生成的实体,重新加载时清理
+#[derive(Component)]
+pub struct LevelEntity;
+
+#[derive(Component)]
+pub struct TowerSlot;
+
+pub struct LevelPlugin;
+
+impl Plugin for LevelPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .add_asset::<Level>()
+        .init_asset_loader::<LevelLoader>()
+        .register_type::<PlayerStats>()
+        .init_resource::<PlayerStats>()
+        .add_startup_system(load_level)
+        .add_system_set(SystemSet::on_enter(GameState::Playing).with_system(level_enter))
+        .add_system_set(SystemSet::on_update(GameState::Playing).with_system(level_spawn));
+    }
+}
+
+fn load_level(
+    mut commands:Commands,
+    asset:Res<AssetServer>
+){
+    //cargo run -- levels/xxx.level.ron
+    let path = std::env::args().nth(1).unwrap_or_else(|| DEFAULT_LEVEL.to_string());
+    info!("load level {}",path);
+    commands.insert_resource(CurrentLevel{
+        handle:asset.load(path.as_str()),
+        path,
+    });
+}
+
+//加载完成后第一次建造地图
+fn level_enter(
+    mut commands:Commands,
+    mut meshes:ResMut<Assets<Mesh>>,
+    mut materials:ResMut<Assets<StandardMaterial>>,
+    current:Res<CurrentLevel>,
+    levels:Res<Assets<Level>>,
+    assets:Res<GameAssets>,
+    spawned:Query<Entity,With<LevelEntity>>
+){
+    let level = match levels.get(&current.handle) {
+        Some(level) => level,
+        None => return
+    };
+    for e in spawned.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+    build_level(&mut commands, &mut meshes, &mut materials, &assets, level, &[]);
+    for route in 0..level.paths.len() {
+        spawn_factory(&mut commands, level, route);
+    }
+    commands.insert_resource(PlayerStats{
+        gold:level.starting_gold,
+        lives:level.lives,
+        score:0,
+    });
+    commands.insert_resource(WaveState::new(level.waves.clone()));
+    commands.insert_resource(NavGrid::new(level));
+    info!("level {} ready",level.name);
+}
+
+//关卡文件热重载或编辑器修改后只重建地形、塔位和出怪点,金币、生命、波次进度和建好的塔都保留
+fn level_spawn(
+    mut commands:Commands,
+    mut events:EventReader<AssetEvent<Level>>,
+    mut meshes:ResMut<Assets<Mesh>>,
+    mut materials:ResMut<Assets<StandardMaterial>>,
+    mut waves:ResMut<WaveState>,
+    mut nav:ResMut<NavGrid>,
+    mut factories:Query<(Entity,&mut TargetFactory)>,
+    current:Res<CurrentLevel>,
+    levels:Res<Assets<Level>>,
+    assets:Res<GameAssets>,
+    geometry:Query<Entity,(With<LevelEntity>,Without<TowerSite>,Without<TargetFactory>)>,
+    sites:Query<&Transform,With<TowerSite>>
+){
+    for event in events.iter() {
+        match event {
+            AssetEvent::Modified { handle } if *handle == current.handle => {},
+            _ => continue
+        }
+        let level = match levels.get(&current.handle) {
+            Some(level) => level,
+            None => continue
+        };
+        for e in geometry.iter() {
+            commands.entity(e).despawn_recursive();
+        }
+        let occupied:Vec<Vec3> = sites.iter().map(|t| t.translation).collect();
+        build_level(&mut commands, &mut meshes, &mut materials, &assets, level, &occupied);
+
+        //还在的路径沿用原来的出怪点,排队的敌人照常出;删掉的路径上没出的敌人不再计数
+        let mut routes = vec![false;level.paths.len()];
+        for (e,mut factory) in factories.iter_mut() {
+            if factory.route < level.paths.len() {
+                factory.path = level.route(factory.route);
+                routes[factory.route] = true;
+            } else {
+                waves.remaining = waves.remaining.saturating_sub(factory.pending.len() as u32);
+                commands.entity(e).despawn_recursive();
+            }
+        }
+        for (route,_) in routes.iter().enumerate().filter(|(_,exists)| !**exists) {
+            spawn_factory(&mut commands, level, route);
+        }
+
+        waves.waves = level.waves.clone();
+        waves.current = waves.current.min(waves.waves.len());
+
+        let version = nav.version;
+        *nav = NavGrid::new(level);
+        nav.version = version;
+        nav.rebuild(occupied.into_iter());
+        info!("level {} reloaded",level.name);
+    }
+}
+
+fn spawn_factory(
+    commands:&mut Commands,
+    level:&Level,
+    route:usize
+){
+    commands.spawn(SpatialBundle::default())
+    .insert(TargetFactory{
+        spawn_timer: Timer::from_seconds(1.0, TimerMode::Repeating),
+        route,
+        path:level.route(route),
+        ..default()
+    })
+    .insert(LevelEntity)
+    .insert(Name::new("TowerFactory"));
+}
+
+//地形,空塔位和灯光;occupied是已经建了塔的位置,那里不再放空塔位
+pub fn build_level(
+    commands:&mut Commands,
+    meshes:&mut Assets<Mesh>,
+    materials:&mut Assets<StandardMaterial>,
+    assets:&GameAssets,
+    level:&Level,
+    occupied:&[Vec3]
+){
+    //ground
+    commands.spawn(PbrBundle{
+        transform:Transform::from_xyz(0.0,0.0,0.0),
+        mesh:meshes.add(Mesh::from(shape::Plane{
+            size:level.ground.size
+        })),
+        material:materials.add(StandardMaterial {
+            base_color:level.ground.color,
+            perceptual_roughness:0.998,
+            ..default()
+        }),
+        ..default()
+    })
+    .insert(PickableMesh::default())
+    .insert(LevelEntity)
+    .insert(Name::new("Ground"));
+
+    //tower base
+    for slot in level.slots.iter() {
+        let position = Vec3::new(slot.position.x,0.0,slot.position.y);
+        if occupied.iter().any(|p| (*p * Vec3::new(1.,0.,1.)).distance(position) < 0.1) {
+            continue;
+        }
+        spawn_tower_slot(
+            commands, materials, assets,
+            position,
+            Quat::from_rotation_y(slot.rotation.to_radians())
+        );
+    }
+
+    //light
+    commands.spawn(DirectionalLightBundle{
+        directional_light:DirectionalLight {
+            shadows_enabled:level.lighting.shadows,
+            illuminance:level.lighting.illuminance,
+            ..default()
+        },
+        transform:Transform {
+            translation: Vec3::new(0.0, 2.0, 0.0),
+            rotation: Quat::from_euler(
+                EulerRot::YXZ,
+                level.lighting.yaw.to_radians(),
+                level.lighting.pitch.to_radians(),
+                0.
+            ),
+            ..default()
+        },
+        ..default()
+    })
+    .insert(LevelEntity)
+    .insert(Name::new("Sun"));
+
+    commands.insert_resource(AmbientLight{
+        color:level.lighting.ambient_color,
+        brightness:level.lighting.ambient_brightness,
+    });
+}
+
+pub fn spawn_tower_slot(
+    commands:&mut Commands,
+    materials:&mut Assets<StandardMaterial>,
+    assets:&GameAssets,
+    position:Vec3,
+    rotation:Quat,
+) -> Entity {
+    let default_color = materials.add(Color::rgba(0.3,0.5, 0.3, 0.3).into());
+    let selected_color = materials.add(Color::rgba(0.3,0.9, 0.3, 0.9).into());
+
+    commands
+    .spawn(SpatialBundle::from_transform(Transform {
+        translation: position,
+        rotation,
+        scale: Vec3::ONE
+    }))
+    .insert(assets.mesh("tower_base"))
+    .insert(default_color.clone())
+    .insert(Highlighting{
+        initial: default_color.clone(),
+        hovered: Some(selected_color.clone()),
+        pressed: Some(selected_color.clone()),
+        selected: Some(selected_color.clone()),
+    })
+    .insert(NotShadowCaster)
+    .insert(PickableBundle::default())
+    .insert(TowerSlot)
+    .insert(LevelEntity)
+    .insert(Name::new("TowerBase"))
+    .with_children(|cb|{
+        cb.spawn(SceneBundle{
+            scene:assets.scene("tower_base"),
+            transform:Transform { translation: Vec3::ZERO, rotation: Quat::IDENTITY, scale: Vec3::new(0.99,0.99,0.99) },
+            ..default()
+        });
+    })
+    .id()
+}
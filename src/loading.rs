@@ -0,0 +1,167 @@
+use bevy::asset::LoadState;
+use bevy::prelude::*;
+
+use crate::assets::*;
+use crate::level::*;
+
+//资源加载完才进入游戏
+#[derive(Clone,Copy,PartialEq,Eq,Hash,Debug)]
+pub enum GameState{
+    Loading,
+    Playing,
+    //有资源缺失或解析失败
+    Failed
+}
+
+//加载失败的文件
+#[derive(Resource,Default)]
+pub struct LoadErrors(pub Vec<String>);
+
+#[derive(Component)]
+pub struct LoadingScreen;
+
+#[derive(Component)]
+pub struct LoadingBar;
+
+pub struct LoadingPlugin;
+
+impl Plugin for LoadingPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .add_state(GameState::Loading)
+        .init_resource::<LoadErrors>()
+        .add_system_set(SystemSet::on_enter(GameState::Loading).with_system(create_loading_screen))
+        .add_system_set(SystemSet::on_update(GameState::Loading).with_system(loading_progress))
+        .add_system_set(SystemSet::on_exit(GameState::Loading).with_system(remove_loading_screen))
+        .add_system_set(SystemSet::on_enter(GameState::Failed).with_system(create_error_screen));
+    }
+}
+
+fn create_loading_screen(
+    mut commands:Commands,
+    assets:Res<GameAssets>
+){
+    commands.spawn(NodeBundle{
+        style:Style{
+            size:Size::new(Val::Percent(100.),Val::Percent(100.)),
+            flex_direction:FlexDirection::Column,
+            justify_content:JustifyContent::Center,
+            align_items:AlignItems::Center,
+            ..default()
+        },
+        background_color:Color::rgb(0.08,0.08,0.1).into(),
+        ..default()
+    })
+    .insert(LoadingScreen)
+    .insert(Name::new("LoadingScreen"))
+    .with_children(|cb|{
+        cb.spawn(TextBundle::from_section(
+            "Loading...",
+            TextStyle { font: assets.font("ui"), font_size: 28., color: Color::WHITE }
+        ).with_style(Style{
+            margin:UiRect::bottom(Val::Px(16.)),
+            ..default()
+        }));
+        //进度条
+        cb.spawn(NodeBundle{
+            style:Style{
+                size:Size::new(Val::Px(400.),Val::Px(20.)),
+                ..default()
+            },
+            background_color:Color::rgb(0.25,0.25,0.3).into(),
+            ..default()
+        })
+        .with_children(|cb|{
+            cb.spawn(NodeBundle{
+                style:Style{
+                    size:Size::new(Val::Percent(0.),Val::Percent(100.)),
+                    ..default()
+                },
+                background_color:Color::rgb(0.3,0.8,0.4).into(),
+                ..default()
+            })
+            .insert(LoadingBar);
+        });
+    });
+}
+
+fn loading_progress(
+    mut state:ResMut<State<GameState>>,
+    mut errors:ResMut<LoadErrors>,
+    mut bars:Query<&mut Style,With<LoadingBar>>,
+    asset_server:Res<AssetServer>,
+    assets:Res<GameAssets>,
+    current:Option<Res<CurrentLevel>>
+){
+    let mut handles = assets.handles();
+    if let Some(current) = current {
+        handles.push(current.handle.clone_untyped());
+    }
+
+    let mut loaded = 0;
+    for handle in handles.iter() {
+        match asset_server.get_load_state(handle) {
+            LoadState::Loaded => loaded += 1,
+            LoadState::Failed => {
+                let path = asset_server.get_handle_path(handle)
+                .map(|p| p.path().display().to_string())
+                .unwrap_or_else(|| format!("{:?}",handle.id));
+                errors.0.push(format!("assets/{}: failed to load",path));
+            },
+            //Unloaded是还没开始加载,和Loading一样继续等
+            _ => {}
+        }
+    }
+
+    for mut style in bars.iter_mut() {
+        style.size.width = Val::Percent(loaded as f32 / handles.len() as f32 * 100.);
+    }
+
+    if !errors.0.is_empty() {
+        errors.0.sort();
+        errors.0.dedup();
+        for message in errors.0.iter() {
+            error!("{}",message);
+        }
+        state.set(GameState::Failed).ok();
+    } else if loaded == handles.len() {
+        info!("all {} assets loaded",loaded);
+        state.set(GameState::Playing).ok();
+    }
+}
+
+fn remove_loading_screen(
+    mut commands:Commands,
+    screens:Query<Entity,With<LoadingScreen>>
+){
+    for e in screens.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+}
+
+fn create_error_screen(
+    mut commands:Commands,
+    errors:Res<LoadErrors>,
+    assets:Res<GameAssets>
+){
+    //字体本身也可能加载失败,这时只能看日志
+    let style = TextStyle { font: assets.font("ui"), font_size: 20., color: Color::rgb(1.0,0.5,0.4) };
+    let mut lines = vec!["Failed to load assets:".to_string()];
+    lines.extend(errors.0.iter().map(|message| format!("  {}",message)));
+    lines.push("Check that the files exist and are valid.".to_string());
+
+    commands.spawn(NodeBundle{
+        style:Style{
+            size:Size::new(Val::Percent(100.),Val::Percent(100.)),
+            justify_content:JustifyContent::Center,
+            align_items:AlignItems::Center,
+            ..default()
+        },
+        background_color:Color::rgb(0.1,0.05,0.05).into(),
+        ..default()
+    })
+    .insert(Name::new("LoadErrorScreen"))
+    .with_children(|cb|{
+        cb.spawn(TextBundle::from_section(lines.join("\n"), style));
+    });
+}
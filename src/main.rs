@@ -1,6 +1,5 @@
-use bevy::{prelude::*, pbr::NotShadowCaster};
+use bevy::prelude::*;
 use bevy_inspector_egui::WorldInspectorPlugin;
-use std::f32::consts::*;
 // use rand::prelude::*;
 use leafwing_input_manager::prelude::*;
 use bevy_mod_picking::*;
@@ -13,6 +12,25 @@ mod tower;
 mod assets;
 mod input;
 mod ui;
+mod level;
+mod wave;
+mod editor;
+mod placement;
+mod navigation;
+mod speed;
+mod range;
+mod healthbar;
+mod settings;
+mod feedback;
+mod controls;
+mod gamepad;
+mod sound;
+mod loading;
+mod benchmark;
+mod tuning;
+mod events;
+mod boss;
+mod abilities;
 
 pub use bullet::*;
 pub use target::*;
@@ -20,6 +38,25 @@ pub use tower::*;
 pub use assets::*;
 pub use input::*;
 pub use ui::*;
+pub use level::*;
+pub use wave::*;
+pub use editor::*;
+pub use placement::*;
+pub use navigation::*;
+pub use speed::*;
+pub use range::*;
+pub use healthbar::*;
+pub use settings::*;
+pub use feedback::*;
+pub use controls::*;
+pub use gamepad::*;
+pub use sound::*;
+pub use loading::*;
+pub use benchmark::*;
+pub use tuning::*;
+pub use events::*;
+pub use boss::*;
+pub use abilities::*;
 
 fn main() {
     App::new()
@@ -34,12 +71,37 @@ fn main() {
             },
             ..default()
         }
+    ).set(
+        //数值和关卡文件改动后自动重载
+        AssetPlugin {
+            watch_for_changes:true,
+            ..default()
+        }
     ))
     // .add_plugin(HikariPlugin)
     .add_plugin(AtmospherePlugin)
     .add_startup_system(setup)
     .add_plugin(WorldInspectorPlugin::new())
     .add_plugin(GameAssetsPlugin)
+    .add_plugin(LoadingPlugin)
+    .add_plugin(TuningPlugin)
+    .add_plugin(GameEventsPlugin)
+    .add_plugin(BossPlugin)
+    .add_plugin(EnemyAbilityPlugin)
+    .add_plugin(LevelPlugin)
+    .add_plugin(WavePlugin)
+    .add_plugin(LevelEditorPlugin)
+    .add_plugin(PlacementPlugin)
+    .add_plugin(NavigationPlugin)
+    .add_plugin(GameSpeedPlugin)
+    .add_plugin(RangeRingPlugin)
+    .add_plugin(HealthBarPlugin)
+    .add_plugin(SettingsPlugin)
+    .add_plugin(HitFeedbackPlugin)
+    .add_plugin(ControlsPlugin)
+    .add_plugin(GamepadControlPlugin)
+    .add_plugin(SoundPlugin)
+    .add_plugin(BenchmarkPlugin)
     .add_plugin(TargetPlugin)
     .add_plugin(TowerPlugin)
     .add_plugin(BulletPlugin)
@@ -52,17 +114,17 @@ fn main() {
 
 fn setup(
     mut commands:Commands,
-    mut meshes:ResMut<Assets<Mesh>>,
-    mut materials:ResMut<Assets<StandardMaterial>>,
-    assets:Res<GameAssets>,
+    controls:Res<Controls>,
     // audio:Res<Audio>,
     // audio_skin:Res<Assets<AudioSink>>
 ){
     //camera
+    let rts_camera = RtsCamera::looking_at(Vec3::new(2.0,2.0,5.0), Vec3::ZERO);
     commands.spawn(Camera3dBundle{
-        transform:Transform::from_xyz(2.0,2.0, 5.0).looking_at(Vec3::ZERO, Vec3::Y),
+        transform:rts_camera.transform(),
         ..default()
     })
+    .insert(rts_camera)
     .insert(PickingCameraBundle::default())
     .insert(HikariSettings{
         indirect_bounces:2,
@@ -71,93 +133,10 @@ fn setup(
     .insert(AtmosphereCamera::default())
     .insert(Name::new("MainCamera"));
 
-    //ground
-    commands.spawn(PbrBundle{
-        transform:Transform::from_xyz(0.0,0.0,0.0),
-        mesh:meshes.add(Mesh::from(shape::Plane{
-            size:50.
-        })),
-        material:materials.add(StandardMaterial { 
-            base_color:Color::DARK_GREEN,
-            perceptual_roughness:0.998,
-            ..default()
-        }),
-        ..default()
-    })
-    .insert(Name::new("Ground"));
-
-    //tower base
-    let map = [
-        (-1.5,-2.,0.),(0.,-2.,0.),(1.5,-2.,0.),
-        (-1.5,2.,0.),(0.,2.,0.),(1.5,2.,0.)
-        ];
-    for i in 0..map.len() {
-        let x = map[i].0;
-        let z = map[i].1;
-        let angle = map[i].2;
-
-        let default_color = materials.add(Color::rgba(0.3,0.5, 0.3, 0.3).into());
-        let selected_color = materials.add(Color::rgba(0.3,0.9, 0.3, 0.9).into());
-
-        commands
-        .spawn(SpatialBundle::from_transform(Transform { 
-            translation: Vec3::new(x,0.0,z), 
-            rotation: Quat::IDENTITY, 
-            scale: Vec3::ONE 
-        }))
-        // .insert(meshes.add(Mesh::from(shape::Capsule::default())))
-        // .insert(meshes.add(Mesh::from(shape::Box::new(1.,1.,1.))))
-        .insert(assets.tower_base_mesh.clone())
-        // .insert(assets.tower_base_1.clone())
-        .insert(default_color.clone())
-        .insert(Highlighting{
-            initial: default_color.clone(),
-            hovered: Some(selected_color.clone()),
-            pressed: Some(selected_color.clone()),
-            selected: Some(selected_color.clone()),
-        })
-        .insert(NotShadowCaster)
-        .insert(PickableBundle::default())
-        .insert(Name::new("TowerBase"))
-        .with_children(|cb|{
-            cb.spawn(SceneBundle{
-                scene:assets.tower_base.clone(),
-                transform:Transform { translation: Vec3::ZERO, rotation: Quat::IDENTITY, scale: Vec3::new(0.99,0.99,0.99) },
-                ..default()
-            });
-        });
-    }
-    
-
-    //target factory
-    commands.spawn(SpatialBundle::default())
-    .insert(TargetFactory{ spawn_timer: Timer::from_seconds(2.5, TimerMode::Repeating) })
-    .insert(Name::new("TowerFactory"));
-
-    //light
-    commands.spawn(DirectionalLightBundle{
-        directional_light:DirectionalLight { 
-            shadows_enabled:true,
-            illuminance:15000.,
-            ..default()
-        },
-        transform:Transform {
-            translation: Vec3::new(0.0, 2.0, 0.0),
-            rotation: Quat::from_rotation_x(-PI / 4.),
-            ..default()
-        },
-        ..default()
-    })
-    .insert(Name::new("Sun"));
-
     //fogs
     //input
     commands.spawn(InputManagerBundle{
         action_state:ActionState::default(),
-        input_map:InputMap::default()
-        .set_gamepad(Gamepad{id:0})
-        .insert(DualAxis::left_stick(), Action::Move)
-        .insert(VirtualDPad::wasd(), Action::Move)
-        .build()
+        input_map:controls.input_map()
     }).insert(PlayerInput);
 }
\ No newline at end of file
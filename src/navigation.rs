@@ -0,0 +1,305 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use bevy::prelude::*;
+use bevy::utils::FloatOrd;
+
+use crate::level::*;
+use crate::loading::*;
+use crate::target::*;
+
+//塔占据的半径
+pub const OBSTACLE_RADIUS:f32 = 0.45;
+
+//迷宫地图的导航网格,塔建好或卖掉后更新
+#[derive(Resource,Default)]
+pub struct NavGrid{
+    pub enabled:bool,
+    pub cell:f32,
+    pub size:usize,
+    pub origin:Vec2,
+    pub blocked:Vec<bool>,
+    pub spawns:Vec<Vec3>,
+    pub goal:Vec3,
+    //每次重建加一,地面单位据此重新寻路
+    pub version:u32,
+}
+
+//阻挡地面单位的物体
+#[derive(Component)]
+pub struct NavObstacle;
+
+#[derive(Component,Default)]
+pub struct GroundUnit{
+    pub nav_version:Option<u32>
+}
+
+impl NavGrid {
+    pub fn new(level:&Level) -> Self {
+        let cell = 0.5;
+        let size = (level.ground.size / cell).ceil() as usize;
+        NavGrid{
+            enabled:level.maze,
+            cell,
+            size,
+            origin:Vec2::splat(-level.ground.size * 0.5),
+            blocked:vec![false;size*size],
+            spawns:level.paths.iter().map(|p| Vec3::new(p.spawn.x,0.,p.spawn.y)).collect(),
+            goal:Vec3::new(level.goal.x,0.,level.goal.y),
+            version:0,
+        }
+    }
+
+    pub fn cell_of(&self,position:Vec3) -> Option<usize> {
+        let p = (Vec2::new(position.x,position.z) - self.origin) / self.cell;
+        if p.x < 0. || p.y < 0. {
+            return None;
+        }
+        let (x,y) = (p.x as usize,p.y as usize);
+        (x < self.size && y < self.size).then(|| y * self.size + x)
+    }
+
+    pub fn center_of(&self,index:usize) -> Vec3 {
+        let x = (index % self.size) as f32 + 0.5;
+        let y = (index / self.size) as f32 + 0.5;
+        Vec3::new(self.origin.x + x * self.cell,0.,self.origin.y + y * self.cell)
+    }
+
+    fn cells_around(&self,position:Vec3,radius:f32) -> Vec<usize> {
+        let steps = (radius / self.cell).ceil() as i32;
+        let mut cells = vec![];
+        for dx in -steps..=steps {
+            for dz in -steps..=steps {
+                let p = position + Vec3::new(dx as f32,0.,dz as f32) * self.cell;
+                if let Some(index) = self.cell_of(p) {
+                    if self.center_of(index).distance(position * Vec3::new(1.,0.,1.)) <= radius {
+                        cells.push(index);
+                    }
+                }
+            }
+        }
+        cells
+    }
+
+    pub fn rebuild(&mut self,obstacles:impl Iterator<Item = Vec3>) {
+        self.blocked.iter_mut().for_each(|b| *b = false);
+        for position in obstacles {
+            for index in self.cells_around(position, OBSTACLE_RADIUS) {
+                self.blocked[index] = true;
+            }
+        }
+        self.version = self.version.wrapping_add(1);
+    }
+
+    //在position建塔后,出生点是否还能到达终点
+    pub fn blocks_route(&self,position:Vec3) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let cells = self.cells_around(position, OBSTACLE_RADIUS);
+        //不能压住出生点和终点
+        let mut ends = self.spawns.iter().chain(std::iter::once(&self.goal)).filter_map(|p| self.cell_of(*p));
+        if ends.any(|end| cells.contains(&end)) {
+            return true;
+        }
+        let mut blocked = self.blocked.clone();
+        for index in cells {
+            blocked[index] = true;
+        }
+        self.spawns.iter().any(|spawn| self.find_path_in(&blocked,*spawn,self.goal).is_none())
+    }
+
+    pub fn find_path(&self,from:Vec3,to:Vec3) -> Option<Vec<Vec3>> {
+        self.find_path_in(&self.blocked,from,to)
+    }
+
+    fn find_path_in(&self,blocked:&[bool],from:Vec3,to:Vec3) -> Option<Vec<Vec3>> {
+        let start = self.cell_of(from)?;
+        let goal = self.cell_of(to)?;
+        let size = self.size as i32;
+        let heuristic = |index:usize|{
+            let dx = ((index % self.size) as f32 - (goal % self.size) as f32).abs();
+            let dy = ((index / self.size) as f32 - (goal / self.size) as f32).abs();
+            dx.max(dy) + (std::f32::consts::SQRT_2 - 1.) * dx.min(dy)
+        };
+
+        let mut cost = vec![f32::INFINITY;blocked.len()];
+        let mut came_from = vec![usize::MAX;blocked.len()];
+        let mut open = BinaryHeap::new();
+        cost[start] = 0.;
+        open.push((Reverse(FloatOrd(heuristic(start))),start));
+
+        while let Some((_,current)) = open.pop() {
+            if current == goal {
+                let mut cells = vec![goal];
+                let mut index = goal;
+                while index != start {
+                    index = came_from[index];
+                    cells.push(index);
+                }
+                cells.reverse();
+                return Some(self.simplify(&cells,to));
+            }
+            let (cx,cy) = ((current % self.size) as i32,(current / self.size) as i32);
+            for (dx,dy) in [(1,0),(-1,0),(0,1),(0,-1),(1,1),(1,-1),(-1,1),(-1,-1)] {
+                let (nx,ny) = (cx + dx,cy + dy);
+                if nx < 0 || ny < 0 || nx >= size || ny >= size {
+                    continue;
+                }
+                let next = (ny * size + nx) as usize;
+                //不能穿墙角
+                let diagonal = dx != 0 && dy != 0;
+                if diagonal && (blocked[(cy * size + nx) as usize] || blocked[(ny * size + cx) as usize]) {
+                    continue;
+                }
+                if blocked[next] && next != goal {
+                    continue;
+                }
+                let step = if diagonal { std::f32::consts::SQRT_2 } else { 1. };
+                let new_cost = cost[current] + step;
+                if new_cost < cost[next] {
+                    cost[next] = new_cost;
+                    came_from[next] = current;
+                    open.push((Reverse(FloatOrd(new_cost + heuristic(next))),next));
+                }
+            }
+        }
+        None
+    }
+
+    //去掉共线的格子,只保留拐点
+    fn simplify(&self,cells:&[usize],to:Vec3) -> Vec<Vec3> {
+        let mut points:Vec<Vec3> = vec![];
+        for (i,index) in cells.iter().enumerate() {
+            if i > 0 && i + 1 < cells.len() {
+                let a = cells[i-1] as i32 - *index as i32;
+                let b = *index as i32 - cells[i+1] as i32;
+                if a == b {
+                    continue;
+                }
+            }
+            points.push(self.center_of(*index));
+        }
+        if let Some(last) = points.last_mut() {
+            *last = to;
+        }
+        points
+    }
+}
+
+pub struct NavigationPlugin;
+
+impl Plugin for NavigationPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_resource::<NavGrid>()
+        .add_system_to_stage(CoreStage::PostUpdate, nav_rebuild)
+        .add_system_set(SystemSet::on_update(GameState::Playing).with_system(nav_repath));
+    }
+}
+
+fn nav_rebuild(
+    mut nav:ResMut<NavGrid>,
+    obstacles:Query<&Transform,With<NavObstacle>>,
+    added:Query<(),Added<NavObstacle>>,
+    removed:RemovedComponents<NavObstacle>
+){
+    if !nav.enabled {
+        return;
+    }
+    if nav.is_added() || !added.is_empty() || removed.iter().next().is_some() {
+        nav.rebuild(obstacles.iter().map(|t| t.translation));
+    }
+}
+
+fn nav_repath(
+    nav:Res<NavGrid>,
+    mut units:Query<(&mut GroundUnit,&mut PathFollower,&Transform),Without<TargetDeath>>
+){
+    if !nav.enabled {
+        return;
+    }
+    for (mut unit,mut follower,transform) in units.iter_mut() {
+        if unit.nav_version == Some(nav.version) {
+            continue;
+        }
+        unit.nav_version = Some(nav.version);
+        if let Some(path) = nav.find_path(transform.translation,nav.goal) {
+            follower.waypoints = path;
+            follower.next = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //每格1个单位,原点在(0,0),格子(x,y)的中心是(x+0.5,0,y+0.5)
+    fn grid(size:usize) -> NavGrid {
+        NavGrid{
+            enabled:true,
+            cell:1.,
+            size,
+            origin:Vec2::ZERO,
+            blocked:vec![false;size*size],
+            ..default()
+        }
+    }
+
+    fn center(x:usize,y:usize) -> Vec3 {
+        Vec3::new(x as f32 + 0.5,0.,y as f32 + 0.5)
+    }
+
+    fn block(grid:&mut NavGrid,x:usize,y:usize) {
+        let size = grid.size;
+        grid.blocked[y * size + x] = true;
+    }
+
+    #[test]
+    fn path_goes_around_obstacle() {
+        let mut grid = grid(10);
+        //x=5的墙,只在y=8以上留口
+        for y in 0..8 {
+            block(&mut grid, 5, y);
+        }
+        let path = grid.find_path(center(0,0), center(9,0)).expect("there is a way around");
+        assert_eq!(*path.last().unwrap(), center(9,0));
+        assert!(path.iter().all(|p| !grid.blocked[grid.cell_of(*p).unwrap()]));
+        assert!(path.iter().any(|p| p.z > 8.));
+    }
+
+    #[test]
+    fn path_does_not_cut_corners() {
+        let mut grid = grid(3);
+        block(&mut grid, 1, 0);
+        //斜着走会擦过(1,0)的墙角,只能先往上再往右
+        let path = grid.find_path(center(0,0), center(1,1)).unwrap();
+        assert_eq!(path, vec![center(0,0),center(0,1),center(1,1)]);
+    }
+
+    #[test]
+    fn full_wall_blocks_route() {
+        let mut grid = grid(10);
+        grid.spawns = vec![center(0,5)];
+        grid.goal = center(9,5);
+        for y in 0..10 {
+            if y != 5 {
+                block(&mut grid, 5, y);
+            }
+        }
+        assert!(grid.find_path(grid.spawns[0], grid.goal).is_some());
+        //堵上最后一个口
+        assert!(grid.blocks_route(center(5,5)));
+        assert!(!grid.blocks_route(center(2,1)));
+    }
+
+    #[test]
+    fn simplify_drops_collinear_cells() {
+        let grid = grid(10);
+        //(0,0)->(3,0)直走,再(3,0)->(3,2)拐弯
+        let cells = [0,1,2,3,13,23];
+        let to = center(3,2);
+        assert_eq!(grid.simplify(&cells, to), vec![center(0,0),center(3,0),to]);
+    }
+}
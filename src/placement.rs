@@ -0,0 +1,220 @@
+use bevy::prelude::*;
+use bevy::pbr::NotShadowCaster;
+use leafwing_input_manager::prelude::*;
+
+use crate::assets::*;
+use crate::events::*;
+use crate::input::*;
+use crate::level::*;
+use crate::loading::*;
+use crate::navigation::*;
+use crate::target::*;
+use crate::tower::*;
+use crate::tuning::*;
+use crate::ui::*;
+
+//路径两侧不能建造的宽度
+const PATH_CLEARANCE:f32 = 0.6;
+//两座塔之间的最小距离
+const TOWER_SPACING:f32 = 0.9;
+
+//自由建造模式,按G切换,1/2/3选择塔
+#[derive(Resource)]
+pub struct Placement{
+    pub enabled:bool,
+    pub tower:Option<TowerType>,
+    pub grid:f32,
+    pub position:Vec3,
+    pub valid:bool,
+    //寻路检查比较慢,位置和导航网格不变时复用结果
+    blocks_route:Option<(Vec3,u32,bool)>,
+}
+
+impl Default for Placement {
+    fn default() -> Self {
+        Placement { enabled: false, tower: None, grid: 0.5, position: Vec3::ZERO, valid: false, blocks_route: None }
+    }
+}
+
+#[derive(Resource)]
+pub struct PlacementMaterials{
+    pub valid:Handle<StandardMaterial>,
+    pub invalid:Handle<StandardMaterial>,
+}
+
+impl FromWorld for PlacementMaterials {
+    fn from_world(world: &mut World) -> Self {
+        let mut materials = world.resource_mut::<Assets<StandardMaterial>>();
+        PlacementMaterials {
+            valid: materials.add(Color::rgba(0.2,0.9,0.2,0.5).into()),
+            invalid: materials.add(Color::rgba(0.9,0.2,0.2,0.5).into()),
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct PlacementGhost(pub TowerType);
+
+pub struct PlacementPlugin;
+
+impl Plugin for PlacementPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_resource::<Placement>()
+        .init_resource::<PlacementMaterials>()
+        .add_system_set(
+            SystemSet::on_update(GameState::Playing)
+            .with_system(placement_hotkeys)
+            .with_system(placement_preview.after(placement_hotkeys))
+            .with_system(placement_confirm.after(placement_preview))
+        );
+    }
+}
+
+fn placement_hotkeys(
+    mut placement:ResMut<Placement>,
+    actions:Query<&ActionState<Action>,With<PlayerInput>>
+){
+    for action in actions.iter() {
+        if action.just_pressed(Action::BuildMode) {
+            placement.enabled = !placement.enabled;
+            placement.tower = None;
+            info!("grid placement {}",if placement.enabled {"on"} else {"off"});
+        }
+        if !placement.enabled {
+            continue;
+        }
+        let hotkeys = [
+            (Action::BuildCannon,TowerType::Cannon),
+            (Action::BuildBallista,TowerType::Ballista),
+            (Action::BuildBlaster,TowerType::Blaster)
+        ];
+        for (key,tower_type) in hotkeys {
+            if action.just_pressed(key) {
+                placement.tower = Some(tower_type);
+            }
+        }
+        if action.just_pressed(Action::Cancel) {
+            placement.tower = None;
+        }
+    }
+}
+
+fn placement_preview(
+    mut commands:Commands,
+    mut placement:ResMut<Placement>,
+    mut ghosts:Query<(Entity,&PlacementGhost,&mut Transform,&mut Handle<StandardMaterial>)>,
+    materials:Res<PlacementMaterials>,
+    assets:Res<GameAssets>,
+    tuning:Res<Tuning>,
+    cursor:Res<GroundCursor>,
+    stats:Res<PlayerStats>,
+    nav:Res<NavGrid>,
+    levels:Res<Assets<Level>>,
+    current:Res<CurrentLevel>,
+    factories:Query<&TargetFactory>,
+    occupied:Query<&GlobalTransform,Or<(With<TowerSlot>,With<Tower>)>>
+){
+    let (tower_type,position) = match (placement.tower,cursor.position) {
+        (Some(tower_type),Some(position)) => (tower_type,position),
+        _ => {
+            for (e,..) in ghosts.iter() {
+                commands.entity(e).despawn_recursive();
+            }
+            placement.valid = false;
+            return;
+        }
+    };
+
+    let position = (position / placement.grid).round() * placement.grid;
+    let half_size = levels.get(&current.handle).map(|l| l.ground.size * 0.5).unwrap_or_default();
+    let inside = position.x.abs() < half_size && position.z.abs() < half_size;
+    //迷宫地图的路线由塔决定
+    let near_path = !nav.enabled && factories.iter().any(|factory|{
+        factory.path.windows(2).any(|segment| distance_to_segment(position, segment[0], segment[1]) < PATH_CLEARANCE)
+    });
+    let overlap = occupied.iter().any(|t|{
+        (t.translation() * Vec3::new(1.,0.,1.)).distance(position) < TOWER_SPACING
+    });
+    let affordable = stats.gold >= tower_type.cost(&tuning);
+    let blocks_route = match placement.blocks_route {
+        Some((checked,version,blocks)) if checked == position && version == nav.version => blocks,
+        _ => {
+            let blocks = nav.blocks_route(position);
+            placement.blocks_route = Some((position,nav.version,blocks));
+            blocks
+        }
+    };
+
+    placement.position = position;
+    placement.valid = inside && !near_path && !overlap && affordable && !blocks_route;
+    let material = if placement.valid { materials.valid.clone() } else { materials.invalid.clone() };
+
+    let mut found = false;
+    for (e,ghost,mut transform,mut ghost_material) in ghosts.iter_mut() {
+        if ghost.0 != tower_type {
+            commands.entity(e).despawn_recursive();
+            continue;
+        }
+        found = true;
+        transform.translation = position;
+        *ghost_material = material.clone();
+    }
+    if !found {
+        let tower_model = assets.scene(tower_type.asset_key());
+        commands.spawn(SpatialBundle::from_transform(Transform::from_translation(position)))
+        .insert(assets.mesh("tower_base"))
+        .insert(material)
+        .insert(NotShadowCaster)
+        .insert(PlacementGhost(tower_type))
+        .insert(Name::new("PlacementGhost"))
+        .with_children(|cb|{
+            cb.spawn(SceneBundle{
+                scene:tower_model,
+                transform:Transform::from_xyz(0.0,0.15,0.),
+                ..default()
+            });
+        });
+    }
+}
+
+fn placement_confirm(
+    mut commands:Commands,
+    mut materials:ResMut<Assets<StandardMaterial>>,
+    mut stats:ResMut<PlayerStats>,
+    placement:Res<Placement>,
+    assets:Res<GameAssets>,
+    tuning:Res<Tuning>,
+    mouse:Res<Input<MouseButton>>,
+    buttons:Query<&Interaction,With<Button>>,
+    mut built:EventWriter<TowerBuilt>
+){
+    if !mouse.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let tower_type = match placement.tower {
+        Some(tower_type) => tower_type,
+        None => return
+    };
+    //点在按钮上
+    if buttons.iter().any(|i| !matches!(i,Interaction::None)) {
+        return;
+    }
+    if !placement.valid {
+        info!("can't build here!");
+        return;
+    }
+    let cost = tower_type.cost(&tuning);
+    stats.gold = stats.gold.saturating_sub(cost);
+    let site = spawn_tower(&mut commands, &mut materials, &assets, &tuning, tower_type, placement.position, None);
+    built.send(TowerBuilt{ site, tower_type, position: placement.position, cost });
+}
+
+fn distance_to_segment(p:Vec3,a:Vec3,b:Vec3) -> f32 {
+    let p = Vec2::new(p.x,p.z);
+    let a = Vec2::new(a.x,a.z);
+    let b = Vec2::new(b.x,b.z);
+    let ab = b - a;
+    let t = if ab.length_squared() > 0. { ((p - a).dot(ab) / ab.length_squared()).clamp(0.,1.) } else { 0. };
+    p.distance(a + ab * t)
+}
@@ -0,0 +1,151 @@
+use bevy::prelude::*;
+use bevy::pbr::NotShadowCaster;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+use bevy_mod_picking::*;
+
+use crate::loading::*;
+use crate::placement::*;
+use crate::tower::*;
+use crate::tuning::*;
+use crate::ui::*;
+
+const RING_WIDTH:f32 = 0.05;
+const RING_SEGMENTS:u32 = 64;
+
+//贴在地面上的射程圈
+#[derive(Component)]
+pub struct RangeRing{
+    pub kind:RingKind,
+    radius:f32,
+}
+
+#[derive(Clone,Copy,PartialEq,Eq)]
+pub enum RingKind{
+    //当前射程
+    Current,
+    //升级后的射程
+    Upgrade
+}
+
+pub struct RangeRingPlugin;
+
+impl Plugin for RangeRingPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .add_startup_system(create_rings)
+        .add_system_set(SystemSet::on_update(GameState::Playing).with_system(update_rings));
+    }
+}
+
+pub fn ring_mesh(radius:f32) -> Mesh {
+    let inner = (radius - RING_WIDTH).max(0.);
+    let mut positions = vec![];
+    let mut normals = vec![];
+    let mut uvs = vec![];
+    let mut indices = vec![];
+    for i in 0..=RING_SEGMENTS {
+        let angle = i as f32 / RING_SEGMENTS as f32 * std::f32::consts::TAU;
+        let (sin,cos) = angle.sin_cos();
+        positions.push([cos * radius,0.,sin * radius]);
+        positions.push([cos * inner,0.,sin * inner]);
+        normals.push([0.,1.,0.]);
+        normals.push([0.,1.,0.]);
+        uvs.push([i as f32 / RING_SEGMENTS as f32,0.]);
+        uvs.push([i as f32 / RING_SEGMENTS as f32,1.]);
+    }
+    for i in 0..RING_SEGMENTS {
+        let outer = i * 2;
+        indices.extend_from_slice(&[outer,outer + 1,outer + 2,outer + 1,outer + 3,outer + 2]);
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
+
+fn create_rings(
+    mut commands:Commands,
+    mut meshes:ResMut<Assets<Mesh>>,
+    mut materials:ResMut<Assets<StandardMaterial>>
+){
+    let rings = [
+        (RingKind::Current,Color::rgba(1.0,1.0,1.0,0.8)),
+        (RingKind::Upgrade,Color::rgba(1.0,0.8,0.2,0.8))
+    ];
+    for (kind,color) in rings {
+        commands.spawn(PbrBundle{
+            mesh:meshes.add(ring_mesh(1.)),
+            material:materials.add(StandardMaterial{
+                base_color:color,
+                unlit:true,
+                alpha_mode:AlphaMode::Blend,
+                ..default()
+            }),
+            visibility:Visibility{ is_visible:false },
+            ..default()
+        })
+        .insert(RangeRing{kind,radius:1.})
+        .insert(NotShadowCaster)
+        .insert(Name::new("RangeRing"));
+    }
+}
+
+//优先级: 建造预览 > 鼠标悬停的塔 > 选中的塔
+fn update_rings(
+    mut meshes:ResMut<Assets<Mesh>>,
+    mut rings:Query<(&mut RangeRing,&mut Transform,&mut Visibility,&Handle<Mesh>)>,
+    placement:Res<Placement>,
+    tuning:Res<Tuning>,
+    sites:Query<(&Hover,&Selection,&GlobalTransform,&Children),With<TowerSite>>,
+    towers:Query<&Tower>,
+    buttons:Query<(&Interaction,&TowerAction)>
+){
+    let mut current:Option<(Vec3,f32)> = None;
+    let mut upgrade:Option<(Vec3,f32)> = None;
+
+    if let (true,Some(tower_type)) = (placement.enabled,placement.tower) {
+        current = Some((placement.position,tower_type.base_tower(&tuning).range));
+    } else {
+        let tower_of = |children:&Children| children.iter().find_map(|c| towers.get(*c).ok());
+        let hovered = sites.iter().find(|(hover,..)| hover.hovered());
+        let selected = sites.iter().find(|(_,selection,..)| selection.selected());
+        if let Some((_,selection,transform,children)) = hovered.or(selected) {
+            if let Some(tower) = tower_of(children) {
+                let position = transform.translation() * Vec3::new(1.,0.,1.);
+                current = Some((position,tower.range));
+                //鼠标放在升级按钮上时显示升级后的射程
+                let upgrade_hovered = buttons.iter().any(|(interaction,action)|{
+                    matches!(action,TowerAction::Upgrade) && !matches!(interaction,Interaction::None)
+                });
+                if selection.selected() && upgrade_hovered && tower.can_upgrade() {
+                    upgrade = Some((position,tower.upgraded_range()));
+                }
+            }
+        }
+    }
+
+    for (mut ring,mut transform,mut visibility,mesh) in rings.iter_mut() {
+        let shown = match ring.kind {
+            RingKind::Current => current,
+            RingKind::Upgrade => upgrade,
+        };
+        match shown {
+            Some((position,radius)) => {
+                visibility.is_visible = true;
+                transform.translation = position + Vec3::Y * 0.02;
+                if ring.radius != radius {
+                    ring.radius = radius;
+                    if let Some(mesh) = meshes.get_mut(mesh) {
+                        *mesh = ring_mesh(radius);
+                    }
+                }
+            },
+            None => if visibility.is_visible {
+                visibility.is_visible = false;
+            }
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use bevy::prelude::*;
+use bevy_inspector_egui::bevy_egui::{EguiContext, EguiPlugin};
+use bevy_inspector_egui::egui;
+use serde::{Deserialize, Serialize};
+
+use crate::controls::*;
+use crate::sound::*;
+
+//设置保存在工作目录下
+pub const SETTINGS_PATH:&str = "settings.ron";
+
+#[derive(Resource,Clone,PartialEq,Debug,Serialize,Deserialize)]
+#[serde(default)]
+pub struct Settings{
+    //飘字伤害
+    pub damage_numbers:bool,
+    //受击闪白
+    pub hit_flash:bool,
+    //镜头平移速度
+    pub camera_speed:f32,
+    //鼠标移到屏幕边缘时平移
+    pub edge_scroll:bool,
+    //音量,0到1
+    pub master_volume:f32,
+    pub music_volume:f32,
+    pub sfx_volume:f32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            damage_numbers: true,
+            hit_flash: true,
+            camera_speed: 3.0,
+            edge_scroll: true,
+            master_volume: 1.0,
+            music_volume: 0.6,
+            sfx_volume: 0.5
+        }
+    }
+}
+
+impl Settings {
+    //总音量乘分组音量
+    pub fn bus_volume(&self,bus:SoundBus) -> f32 {
+        let volume = match bus {
+            SoundBus::Music => self.music_volume,
+            SoundBus::Sfx => self.sfx_volume,
+        };
+        self.master_volume * volume
+    }
+
+    pub fn load() -> Self {
+        match std::fs::read_to_string(SETTINGS_PATH) {
+            Ok(text) => ron::from_str(&text).unwrap_or_else(|e|{
+                warn!("invalid {}: {}",SETTINGS_PATH,e);
+                Settings::default()
+            }),
+            Err(_) => Settings::default()
+        }
+    }
+
+    pub fn save(&self) {
+        let result = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+        .map_err(|e| e.to_string())
+        .and_then(|text| std::fs::write(SETTINGS_PATH, text).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            error!("save settings failed: {}",e);
+        }
+    }
+}
+
+//F1打开设置窗口
+#[derive(Resource,Default)]
+pub struct SettingsMenu{
+    pub open:bool
+}
+
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.world.contains_resource::<EguiContext>() {
+            app.add_plugin(EguiPlugin);
+        }
+        app
+        .insert_resource(Settings::load())
+        .init_resource::<SettingsMenu>()
+        .add_system(settings_toggle)
+        .add_system(settings_menu.after(settings_toggle));
+    }
+}
+
+fn settings_toggle(
+    mut menu:ResMut<SettingsMenu>,
+    keyboard:Res<Input<KeyCode>>
+){
+    if keyboard.just_pressed(KeyCode::F1) {
+        menu.open = !menu.open;
+    }
+}
+
+fn settings_menu(
+    mut menu:ResMut<SettingsMenu>,
+    mut settings:ResMut<Settings>,
+    mut controls_menu:ResMut<ControlsMenu>,
+    mut egui_context:ResMut<EguiContext>
+){
+    if !menu.open {
+        return;
+    }
+    let mut edited = settings.clone();
+    egui::Window::new("Settings").open(&mut menu.open).show(egui_context.ctx_mut(), |ui|{
+        ui.heading("Feedback");
+        ui.checkbox(&mut edited.damage_numbers, "Damage numbers");
+        ui.checkbox(&mut edited.hit_flash, "Hit flash");
+        ui.separator();
+        ui.heading("Camera");
+        ui.add(egui::Slider::new(&mut edited.camera_speed, 0.5..=10.0).text("Speed"));
+        ui.checkbox(&mut edited.edge_scroll, "Edge scrolling");
+        ui.separator();
+        ui.heading("Audio");
+        ui.add(egui::Slider::new(&mut edited.master_volume, 0.0..=1.0).text("Master"));
+        ui.add(egui::Slider::new(&mut edited.music_volume, 0.0..=1.0).text("Music"));
+        ui.add(egui::Slider::new(&mut edited.sfx_volume, 0.0..=1.0).text("Effects"));
+        ui.separator();
+        if ui.button("Controls...").clicked() {
+            controls_menu.open = true;
+        }
+    });
+    if edited != *settings {
+        edited.save();
+        *settings = edited;
+    }
+}
@@ -0,0 +1,98 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use rand::random;
+
+use crate::settings::*;
+
+#[derive(Clone,Copy,PartialEq,Eq,Hash,Debug)]
+pub enum SoundBus{
+    Music,
+    Sfx
+}
+
+//同一个音效的限制
+#[derive(Clone,Copy,Debug)]
+pub struct VoiceLimit{
+    //两次播放之间的最短间隔
+    pub cooldown:f32,
+    //同时播放的上限
+    pub max_voices:usize,
+    //估计的音效时长,用来判断还有几个在响
+    pub length:f32,
+}
+
+impl Default for VoiceLimit {
+    fn default() -> Self {
+        VoiceLimit { cooldown: 0.05, max_voices: 4, length: 0.5 }
+    }
+}
+
+//播放一次性音效,由sound_play统一处理音量,限流和音调
+pub struct PlaySound{
+    pub source:Handle<AudioSource>,
+    pub bus:SoundBus,
+    pub volume:f32,
+    //随机音调的幅度,0.1表示0.9到1.1倍
+    pub pitch_variation:f32,
+    pub limit:VoiceLimit,
+}
+
+impl PlaySound {
+    pub fn sfx(source:Handle<AudioSource>) -> Self {
+        PlaySound { source, bus: SoundBus::Sfx, volume: 1.0, pitch_variation: 0.1, limit: VoiceLimit::default() }
+    }
+
+    pub fn with_volume(mut self,volume:f32) -> Self {
+        self.volume = volume;
+        self
+    }
+
+    pub fn with_limit(mut self,limit:VoiceLimit) -> Self {
+        self.limit = limit;
+        self
+    }
+}
+
+//每个音效正在响的结束时间
+#[derive(Resource,Default)]
+pub struct SoundManager{
+    voices:HashMap<Handle<AudioSource>,Vec<f32>>,
+}
+
+pub struct SoundPlugin;
+
+impl Plugin for SoundPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_resource::<SoundManager>()
+        .add_event::<PlaySound>()
+        .add_system_to_stage(CoreStage::PostUpdate, sound_play);
+    }
+}
+
+fn sound_play(
+    mut requests:EventReader<PlaySound>,
+    mut manager:ResMut<SoundManager>,
+    audio:Res<Audio>,
+    settings:Res<Settings>,
+    time:Res<Time>
+){
+    let now = time.raw_elapsed_seconds();
+    for request in requests.iter() {
+        let volume = request.volume * settings.bus_volume(request.bus);
+        if volume <= 0. {
+            continue;
+        }
+        let voices = manager.voices.entry(request.source.clone()).or_default();
+        voices.retain(|end| *end > now);
+        let limit = request.limit;
+        let last_start = voices.iter().map(|end| end - limit.length).fold(f32::MIN, f32::max);
+        if voices.len() >= limit.max_voices || now - last_start < limit.cooldown {
+            continue;
+        }
+        voices.push(now + limit.length);
+
+        let pitch = 1.0 + (random::<f32>() * 2. - 1.) * request.pitch_variation;
+        audio.play_with_settings(request.source.clone(), PlaybackSettings { repeat: false, volume, speed: pitch });
+    }
+}
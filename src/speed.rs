@@ -0,0 +1,78 @@
+use bevy::prelude::*;
+use leafwing_input_manager::prelude::*;
+
+use crate::input::*;
+use crate::loading::*;
+
+//游戏速度,通过Time的relative_speed作用到所有用time.delta()的系统
+#[derive(Resource)]
+pub struct GameSpeed{
+    pub paused:bool,
+    pub multiplier:f32,
+}
+
+impl Default for GameSpeed {
+    fn default() -> Self {
+        GameSpeed { paused: false, multiplier: 1.0 }
+    }
+}
+
+impl GameSpeed {
+    pub const SPEEDS:[f32;3] = [1.0,2.0,3.0];
+
+    pub fn fast_forward(&mut self) {
+        let index = Self::SPEEDS.iter().position(|s| *s == self.multiplier).unwrap_or(0);
+        self.multiplier = Self::SPEEDS[(index + 1) % Self::SPEEDS.len()];
+    }
+
+    pub fn label(&self) -> String {
+        if self.paused {
+            "Paused".to_string()
+        } else {
+            format!("{}x",self.multiplier)
+        }
+    }
+}
+
+pub struct GameSpeedPlugin;
+
+impl Plugin for GameSpeedPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_resource::<GameSpeed>()
+        .add_system_set(SystemSet::on_update(GameState::Playing).with_system(game_speed_input))
+        .add_system(game_speed_apply.after(game_speed_input));
+    }
+}
+
+fn game_speed_input(
+    mut speed:ResMut<GameSpeed>,
+    actions:Query<&ActionState<Action>,With<PlayerInput>>
+){
+    for action in actions.iter() {
+        if action.just_pressed(Action::Pause) {
+            speed.paused = !speed.paused;
+            info!("game {}",if speed.paused {"paused"} else {"resumed"});
+        }
+        if action.just_pressed(Action::FastForward) {
+            speed.paused = false;
+            speed.fast_forward();
+            info!("game speed {}",speed.label());
+        }
+    }
+}
+
+fn game_speed_apply(
+    speed:Res<GameSpeed>,
+    mut time:ResMut<Time>
+){
+    if !speed.is_changed() {
+        return;
+    }
+    time.set_relative_speed(speed.multiplier);
+    if speed.paused {
+        time.pause();
+    } else {
+        time.unpause();
+    }
+}
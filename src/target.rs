@@ -1,26 +1,202 @@
+use std::collections::VecDeque;
 use std::f32::consts::PI;
 
 use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_inspector_egui::Inspectable;
 use rand::random;
+use serde::{Deserialize, Serialize};
+use crate::abilities::*;
 use crate::assets::*;
+use crate::events::*;
+use crate::level::*;
+use crate::loading::*;
+use crate::navigation::*;
+use crate::settings::*;
+use crate::sound::*;
+use crate::tuning::*;
+
+//飞行时上下浮动的幅度和频率
+const BOB_AMPLITUDE:f32 = 0.08;
+const BOB_SPEED:f32 = 2.5;
+//转弯时的最大倾斜角度和倾斜跟随速度
+const MAX_BANK:f32 = 0.6;
+const BANK_SCALE:f32 = 0.25;
+const BANK_RESPONSE:f32 = 4.;
+
+//同时播放引擎声的敌人数量,只选离镜头最近的
+const MAX_ENGINE_LOOPS:usize = 4;
+const ENGINE_VOLUME:f32 = 0.6;
+//引擎声从近处开始衰减,到远处听不见
+const ENGINE_NEAR:f32 = 2.;
+const ENGINE_FAR:f32 = 12.;
+
+//正在播放的引擎声,敌人死掉或消失后停止
+#[derive(Resource,Default)]
+pub struct EngineSounds{
+    sinks:HashMap<Entity,Handle<AudioSink>>,
+    //还没创建好就要停的sink
+    stopping:Vec<Handle<AudioSink>>,
+}
 
 #[derive(Component,Reflect,Default)]
 #[reflect(Component)]
 pub struct TargetFactory{
     pub spawn_timer:Timer,
+    //关卡中的路径序号
+    pub route:usize,
+    pub path:Vec<Vec3>,
+    #[reflect(ignore)]
+    pub pending:VecDeque<EnemyType>,
+}
+
+#[derive(Component,Reflect,Default)]
+#[reflect(Component)]
+pub struct PathFollower{
+    pub waypoints:Vec<Vec3>,
+    pub next:usize
+}
+
+#[derive(Inspectable,Component,Clone,Copy,PartialEq,Eq,Hash,Debug,Serialize,Deserialize)]
+pub enum EnemyType{
+    Red,
+    //贴地走,迷宫地图上会绕开塔
+    Crawler,
+    //波次最后出场的首领
+    Mothership,
+    //隐身
+    Phantom,
+    //给附近的敌人回血
+    Medic
+}
+
+//空中和地面单位分开,塔可以只打其中一种
+#[derive(Component,Clone,Copy,PartialEq,Eq,Debug,Deserialize)]
+pub enum MovementLayer{
+    Air,
+    Ground
+}
+
+impl EnemyType {
+    pub const ALL:[EnemyType;5] = [EnemyType::Red,EnemyType::Crawler,EnemyType::Mothership,EnemyType::Phantom,EnemyType::Medic];
+
+    //资源清单里的模型和引擎声
+    pub fn asset_keys(&self) -> (&'static str,&'static str) {
+        match self {
+            EnemyType::Red => ("enemy_red","enemy_engine"),
+            EnemyType::Crawler => ("enemy_crawler","enemy_engine"),
+            EnemyType::Mothership => ("enemy_mothership","enemy_engine"),
+            EnemyType::Phantom => ("enemy_phantom","enemy_engine"),
+            EnemyType::Medic => ("enemy_medic","enemy_engine")
+        }
+    }
+
+    //数值在tuning.ron里
+    pub fn get_target(&self,assets:&GameAssets,tuning:&Tuning) -> (Handle<Scene>,Target,Health) {
+        let speed_roll:f32 = random();
+        let (model,engine) = self.asset_keys();
+        let tuned = tuning.enemy(*self);
+        (
+            assets.scene(model),
+            Target{
+                speed:tuned.speed + speed_roll * tuned.speed_variation,
+                speed_roll,
+                sfx:assets.audio(engine)
+            },
+            Health::new(tuned.health)
+        )
+    }
+
+    //击杀奖励的金币
+    pub fn bounty(&self,tuning:&Tuning) -> u32 {
+        tuning.enemy(*self).bounty
+    }
+
+    pub fn score(&self,tuning:&Tuning) -> u32 {
+        tuning.enemy(*self).score
+    }
 }
 
 #[derive(Component,Reflect,Default)]
 #[reflect(Component)]
 pub struct Target{
     pub speed:f32,
+    //出生时随机的速度比例,改数值后保持不变
+    pub speed_roll:f32,
     pub sfx:Handle<AudioSource>
 }
 
+//飞行姿态,高度由敌人类型决定,上下浮动和转弯倾斜只影响外观
+#[derive(Component,Reflect,Default)]
+#[reflect(Component)]
+pub struct Flight{
+    pub altitude:f32,
+    //浮动幅度,地面单位为0
+    pub bob:f32,
+    pub phase:f32,
+    //水平朝向和当前倾斜角
+    pub heading:Vec3,
+    pub bank:f32,
+    last:Vec3,
+}
+
+impl Flight {
+    pub fn new(layer:MovementLayer,altitude:f32,position:Vec3) -> Self {
+        Flight{
+            altitude,
+            bob:if layer == MovementLayer::Air { BOB_AMPLITUDE } else { 0. },
+            phase:random::<f32>() * 2. * PI,
+            heading:Vec3::ZERO,
+            bank:0.,
+            last:position
+        }
+    }
+
+    pub fn height(&self,elapsed:f32) -> f32 {
+        self.altitude + self.bob * (elapsed * BOB_SPEED + self.phase).sin()
+    }
+}
+
 #[derive(Component,Reflect,Default)]
 #[reflect(Component)]
 pub struct Health{
-    pub value:i32
+    pub value:i32,
+    pub max:i32
+}
+
+impl Health {
+    pub fn new(value:i32) -> Self {
+        Health{value,max:value}
+    }
+
+    //改上限时按比例保留已经受到的伤害
+    pub fn rescale(&mut self,max:i32) {
+        if self.max > 0 && self.max != max {
+            self.value = (self.value as f32 * max as f32 / self.max as f32).ceil() as i32;
+            self.max = max;
+        }
+    }
+}
+
+//护盾,先于生命值扣除
+#[derive(Component,Reflect,Default)]
+#[reflect(Component)]
+pub struct Shield{
+    pub value:i32,
+    pub max:i32
+}
+
+impl Shield {
+    pub fn new(value:i32) -> Self {
+        Shield{value,max:value}
+    }
+
+    //返回护盾没挡住的伤害
+    pub fn absorb(&mut self,damage:i32) -> i32 {
+        let absorbed = damage.min(self.value).max(0);
+        self.value -= absorbed;
+        damage - absorbed
+    }
 }
 
 #[derive(Component)]
@@ -33,10 +209,22 @@ impl Plugin for TargetPlugin  {
         app
         .register_type::<Target>()
         .register_type::<Health>()
+        .register_type::<Shield>()
+        .register_type::<Flight>()
         .register_type::<TargetFactory>()
-        .add_system(target_spawn)
-        .add_system(target_move)
-        .add_system(target_death);
+        .register_type::<PathFollower>()
+        .init_resource::<EngineSounds>()
+        .add_system_set(
+            SystemSet::on_update(GameState::Playing)
+            .with_system(target_spawn)
+            .with_system(target_move)
+            .with_system(target_flight.after(target_move))
+            .with_system(target_death)
+            .with_system(target_rewards)
+            .with_system(target_engine_sound)
+            .with_system(target_hit_sound)
+            .with_system(target_death_sound)
+        );
     }
 }
 
@@ -44,36 +232,74 @@ fn target_spawn(
     mut commands:Commands,
     mut factories:Query<&mut TargetFactory>,
     assets:Res<GameAssets>,
-    time:Res<Time>
+    tuning:Res<Tuning>,
+    nav:Res<NavGrid>,
+    time:Res<Time>,
+    mut spawned:EventWriter<EnemySpawned>
 ){
-    let speed:f32 = random();
     for mut factory in factories.iter_mut() {
+        if factory.pending.is_empty() || factory.path.is_empty() {
+            continue;
+        }
         factory.spawn_timer.tick(time.delta());
 
         if factory.spawn_timer.finished() {
-            commands.spawn(SceneBundle{
-                scene:assets.enemy_red.clone(),
-                transform:Transform{
-                    translation:Vec3::new(-4.,speed+0.5,0.),
-                    scale:Vec3::new(0.5,0.5,0.5),
-                    ..default()
-                },
-                ..default()
-            })
-            .insert(Target{speed:speed+1.0,sfx:assets.enemy_move_audio.clone()})
-            .insert(Health{value:10})
-            .insert(Name::new("Target"));
+            if let Some(enemy_type) = factory.pending.pop_front() {
+                let follower = PathFollower{waypoints:factory.path.clone(),next:1};
+                let (enemy,position) = spawn_enemy(&mut commands, &assets, &tuning, &nav, enemy_type, factory.path[0], follower);
+                spawned.send(EnemySpawned{ enemy, enemy_type, position });
+            }
         }
     }
 }
 
+//在position(只用x和z)生成敌人,高度由敌人类型决定,返回实体和出生位置
+pub fn spawn_enemy(
+    commands:&mut Commands,
+    assets:&GameAssets,
+    tuning:&Tuning,
+    nav:&NavGrid,
+    enemy_type:EnemyType,
+    position:Vec3,
+    follower:PathFollower,
+) -> (Entity,Vec3) {
+    let (model,target,health) = enemy_type.get_target(assets,tuning);
+    let tuned = tuning.enemy(enemy_type);
+    let flight = Flight::new(tuned.layer,tuned.altitude,position);
+    let position = Vec3::new(position.x,tuned.altitude,position.z);
+    let mut entity = commands.spawn(SceneBundle{
+        scene:model,
+        transform:Transform{
+            translation:position,
+            scale:Vec3::new(0.5,0.5,0.5),
+            ..default()
+        },
+        ..default()
+    });
+    entity
+    .insert(target)
+    .insert(health)
+    .insert(enemy_type)
+    .insert(tuned.layer)
+    .insert(flight)
+    .insert(follower)
+    .insert(Name::new("Target"));
+    insert_abilities(&mut entity, tuning.abilities(enemy_type));
+    //空中单位直接飞过迷宫
+    if nav.enabled && tuned.layer == MovementLayer::Ground {
+        entity.insert(GroundUnit::default());
+    }
+    (entity.id(),position)
+}
+
 fn target_move(
     mut commands:Commands,
-    mut alive_queue:Query<(Entity,&Target,&mut Transform),Without<TargetDeath>>,
+    mut alive_queue:Query<(Entity,&Target,&EnemyType,&mut PathFollower,&mut Transform),Without<TargetDeath>>,
     mut death_queue:Query<(Entity,&Target,&mut Transform),With<TargetDeath>>,
     // assets:Res<GameAssets>,
     // audio_skin:Res<Assets<AudioSink>>,
     // audio:Res<Audio>,
+    mut leaked:EventWriter<EnemyLeaked>,
     time:Res<Time>
 ){
     for (e,_,mut transform) in death_queue.iter_mut() {
@@ -83,28 +309,206 @@ fn target_move(
 
         if transform.translation.y < 0. {
             commands.entity(e).despawn_recursive();
-            info!("target fallout!")
         }
     }
 
-    for (e,target,mut transform) in alive_queue.iter_mut() {
-        transform.translation.x += target.speed * time.delta_seconds();
+    for (e,target,enemy_type,mut follower,mut transform) in alive_queue.iter_mut() {
+        if let Some(waypoint) = follower.waypoints.get(follower.next) {
+            //保持飞行高度
+            let goal = Vec3::new(waypoint.x,transform.translation.y,waypoint.z);
+            let to_goal = goal - transform.translation;
+            let step = target.speed * time.delta_seconds();
+            if to_goal.length() <= step {
+                transform.translation = goal;
+                follower.next += 1;
+            } else {
+                transform.translation += to_goal.normalize() * step;
+            }
+        }
 
-        if transform.translation.x >= 4. {
+        if follower.next >= follower.waypoints.len() {
             commands.entity(e).despawn_recursive();
-            info!("target destroy!")
+            leaked.send(EnemyLeaked{ enemy: e, enemy_type: *enemy_type });
+        }
+    }
+}
+
+//按敌人类型的高度上下浮动,转弯时向内侧倾斜
+fn target_flight(
+    mut query:Query<(&mut Flight,&mut Transform),(With<Target>,Without<TargetDeath>)>,
+    time:Res<Time>
+){
+    let dt = time.delta_seconds();
+    if dt <= 0. {
+        return;
+    }
+    for (mut flight,mut transform) in query.iter_mut() {
+        let moved = (transform.translation - flight.last) * Vec3::new(1.,0.,1.);
+        flight.last = transform.translation;
+        let mut turn = 0.;
+        if moved.length_squared() > 1e-8 {
+            let heading = moved.normalize();
+            if flight.heading != Vec3::ZERO {
+                //向左转为正
+                let angle = flight.heading.angle_between(heading);
+                turn = angle.copysign(flight.heading.cross(heading).y) / dt;
+            }
+            flight.heading = heading;
+        }
+        let target_bank = (-turn * BANK_SCALE).clamp(-MAX_BANK,MAX_BANK);
+        flight.bank += (target_bank - flight.bank) * (dt * BANK_RESPONSE).min(1.);
+
+        transform.translation.y = flight.height(time.elapsed_seconds());
+        if flight.heading != Vec3::ZERO {
+            transform.rotation = Quat::from_axis_angle(flight.heading,flight.bank);
         }
     }
 }
 
 fn target_death(
     mut commands:Commands,
-    query:Query<(Entity,&Health,&Target),Without<TargetDeath>>
+    mut killed:EventWriter<EnemyKilled>,
+    query:Query<(Entity,&Health,&EnemyType,&GlobalTransform),(With<Target>,Without<TargetDeath>)>
 ){
-    for (e,health,_) in query.iter() {
+    for (e,health,enemy_type,transform) in query.iter() {
         if health.value <=0 {
             commands.entity(e).insert(TargetDeath);
-            info!("target death!")
+            killed.send(EnemyKilled{ enemy: e, enemy_type: *enemy_type, position: transform.translation() });
+        }
+    }
+}
+
+//击杀奖励金币和分数,漏掉扣命
+fn target_rewards(
+    mut stats:ResMut<PlayerStats>,
+    mut killed:EventReader<EnemyKilled>,
+    mut leaked:EventReader<EnemyLeaked>,
+    tuning:Res<Tuning>
+){
+    for event in killed.iter() {
+        stats.gold += event.enemy_type.bounty(&tuning);
+        stats.score += event.enemy_type.score(&tuning);
+    }
+    for event in leaked.iter() {
+        stats.lives = stats.lives.saturating_sub(tuning.lives_lost(event.enemy_type));
+    }
+}
+fn target_engine_sound(
+    mut engines:ResMut<EngineSounds>,
+    audio:Res<Audio>,
+    sinks:Res<Assets<AudioSink>>,
+    settings:Res<Settings>,
+    time:Res<Time>,
+    targets:Query<(Entity,&Target,&GlobalTransform),Without<TargetDeath>>,
+    camera:Query<&GlobalTransform,With<Camera3d>>
+){
+    let listener = match camera.get_single() {
+        Ok(camera) => camera.translation(),
+        Err(_) => return
+    };
+    let mut nearest:Vec<(Entity,&Target,f32)> = targets.iter()
+    .map(|(e,target,transform)| (e,target,transform.translation().distance(listener)))
+    .filter(|(..,distance)| *distance < ENGINE_FAR)
+    .collect();
+    nearest.sort_by(|a,b| a.2.total_cmp(&b.2));
+    nearest.truncate(MAX_ENGINE_LOOPS);
+
+    //不在最近几个里的停掉,Drop会让sink继续播放,所以要先stop
+    let engines = &mut *engines;
+    let stopping = &mut engines.stopping;
+    engines.sinks.retain(|e,sink|{
+        let keep = nearest.iter().any(|(near,..)| near == e);
+        if !keep {
+            stopping.push(sink.clone());
         }
+        keep
+    });
+    stopping.retain(|sink|{
+        match sinks.get(sink) {
+            Some(sink) => {
+                sink.stop();
+                false
+            },
+            None => true
+        }
+    });
+
+    let bus = if time.is_paused() { 0. } else { settings.bus_volume(SoundBus::Sfx) };
+    for (e,target,distance) in nearest {
+        let falloff = 1. - ((distance - ENGINE_NEAR) / (ENGINE_FAR - ENGINE_NEAR)).clamp(0.,1.);
+        let volume = bus * ENGINE_VOLUME * falloff;
+        let sink = engines.sinks.entry(e).or_insert_with(||{
+            let weak = audio.play_with_settings(target.sfx.clone(), PlaybackSettings::LOOP.with_volume(volume));
+            sinks.get_handle(weak)
+        });
+        //sink要等下一帧才创建好
+        if let Some(sink) = sinks.get(sink) {
+            sink.set_volume(volume);
+        }
+    }
+}
+
+fn target_hit_sound(
+    mut hits:EventReader<EnemyDamaged>,
+    mut sounds:EventWriter<PlaySound>,
+    assets:Res<GameAssets>
+){
+    for _ in hits.iter() {
+        sounds.send(PlaySound::sfx(assets.audio("enemy_hit")).with_volume(0.5));
+    }
+}
+
+fn target_death_sound(
+    mut killed:EventReader<EnemyKilled>,
+    mut sounds:EventWriter<PlaySound>,
+    assets:Res<GameAssets>
+){
+    for _ in killed.iter() {
+        sounds.send(PlaySound::sfx(assets.audio("enemy_death")).with_limit(VoiceLimit{ cooldown: 0.05, max_voices: 3, length: 1.0 }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app() -> App {
+        let mut app = App::new();
+        app
+        .init_resource::<Tuning>()
+        .insert_resource(PlayerStats{ gold: 0, lives: 20, score: 0 })
+        .add_event::<EnemyKilled>()
+        .add_event::<EnemyLeaked>()
+        .add_system(target_rewards);
+        app
+    }
+
+    #[test]
+    fn killed_enemy_pays_bounty_and_score() {
+        let mut app = app();
+        let enemy = app.world.spawn_empty().id();
+        app.world.send_event(EnemyKilled{ enemy, enemy_type: EnemyType::Red, position: Vec3::ZERO });
+        app.update();
+
+        let tuning = Tuning::default();
+        let stats = app.world.resource::<PlayerStats>();
+        assert_eq!(stats.gold, EnemyType::Red.bounty(&tuning));
+        assert_eq!(stats.score, EnemyType::Red.score(&tuning));
+        assert_eq!(stats.lives, 20);
+    }
+
+    #[test]
+    fn leaked_boss_costs_its_leak_lives() {
+        let mut app = app();
+        let enemy = app.world.spawn_empty().id();
+        app.world.send_event(EnemyLeaked{ enemy, enemy_type: EnemyType::Red });
+        app.world.send_event(EnemyLeaked{ enemy, enemy_type: EnemyType::Mothership });
+        app.update();
+
+        let tuning = Tuning::default();
+        let stats = app.world.resource::<PlayerStats>();
+        assert_eq!(stats.lives, 20 - 1 - tuning.lives_lost(EnemyType::Mothership));
+        assert_eq!(stats.gold, 0);
+        assert_eq!(stats.score, 0);
     }
-}
\ No newline at end of file
+}
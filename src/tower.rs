@@ -3,82 +3,249 @@ use std::f32::consts::PI;
 use bevy::prelude::*;
 use bevy::utils::FloatOrd;
 use bevy_inspector_egui::Inspectable;
+use rand::random;
+use serde::Deserialize;
 
+use crate::abilities::*;
 use crate::assets::*;
+use crate::loading::*;
 use crate::target::*;
 use crate::bullet::*;
+use crate::events::*;
+use crate::sound::*;
+use crate::tuning::*;
+use crate::level::*;
+use crate::ui::*;
+
+pub const MAX_TIER:u32 = 3;
+//每级增加的射程
+pub const UPGRADE_RANGE:f32 = 0.5;
+//暴击概率和倍数
+pub const CRIT_CHANCE:f32 = 0.1;
+pub const CRIT_MULTIPLIER:i32 = 2;
+//修理每点耐久花的金币
+pub const REPAIR_GOLD_PER_HEALTH:f32 = 0.5;
+
+//把耐久修满要花的金币
+pub fn repair_cost(health:&Health) -> u32 {
+    ((health.max - health.value).max(0) as f32 * REPAIR_GOLD_PER_HEALTH).ceil() as u32
+}
 
 #[derive(Component,Reflect,Default)]
 #[reflect(Component)]
 pub struct Tower{
-    pub shooting_timer:Timer
+    pub shooting_timer:Timer,
+    //装填好了,等目标进入射程就开火
+    pub loaded:bool,
+    pub tier:u32,
+    pub damage:i32,
+    pub range:f32,
+    pub targeting:TargetingMode,
+    //能打空中还是地面单位
+    pub targets:TargetLayers,
+    pub kills:u32,
+    pub damage_dealt:i32,
+    //建造和升级花掉的金币,卖塔时退一半
+    pub invested:u32,
+}
+
+impl Tower {
+    pub fn new(interval:f32,damage:i32,range:f32,cost:u32) -> Self {
+        Tower{
+            shooting_timer: Timer::from_seconds(interval, TimerMode::Repeating),
+            tier: 1,
+            damage,
+            range,
+            invested: cost,
+            ..default()
+        }
+    }
+
+    pub fn fire_rate(&self) -> f32 {
+        1.0 / self.shooting_timer.duration().as_secs_f32()
+    }
+
+    pub fn can_upgrade(&self) -> bool {
+        self.tier < MAX_TIER
+    }
+
+    pub fn upgrade_cost(&self,tower_type:&TowerType,tuning:&Tuning) -> u32 {
+        tower_type.cost(tuning) * self.tier
+    }
+
+    pub fn sell_price(&self) -> u32 {
+        self.invested / 2
+    }
+
+    //升级后的射程
+    pub fn upgraded_range(&self) -> f32 {
+        self.range + UPGRADE_RANGE
+    }
+
+    //升级: 伤害x1.5,射程+0.5,射击间隔x0.85
+    pub fn upgrade(&mut self,cost:u32) {
+        let interval = self.shooting_timer.duration().mul_f32(0.85);
+        self.shooting_timer.set_duration(interval);
+        self.damage = (self.damage as f32 * 1.5).ceil() as i32;
+        self.range = self.upgraded_range();
+        self.tier += 1;
+        self.invested += cost;
+    }
+}
+
+#[derive(Reflect,Clone,Copy,PartialEq,Eq,Debug,Default,Deserialize)]
+pub enum TargetLayers{
+    Air,
+    Ground,
+    #[default]
+    Both
 }
 
-#[derive(Inspectable,Component,Clone,Copy,Debug)]
+impl TargetLayers {
+    pub fn can_hit(&self,layer:MovementLayer) -> bool {
+        match self {
+            TargetLayers::Air => layer == MovementLayer::Air,
+            TargetLayers::Ground => layer == MovementLayer::Ground,
+            TargetLayers::Both => true,
+        }
+    }
+}
+
+#[derive(Reflect,Clone,Copy,PartialEq,Eq,Debug,Default)]
+pub enum TargetingMode{
+    //走得最远的
+    #[default]
+    First,
+    Closest,
+    Strongest,
+    Weakest
+}
+
+impl TargetingMode {
+    pub fn next(&self) -> Self {
+        match self {
+            TargetingMode::First => TargetingMode::Closest,
+            TargetingMode::Closest => TargetingMode::Strongest,
+            TargetingMode::Strongest => TargetingMode::Weakest,
+            TargetingMode::Weakest => TargetingMode::First,
+        }
+    }
+}
+
+#[derive(Inspectable,Component,Clone,Copy,PartialEq,Eq,Hash,Debug,Deserialize)]
 pub enum TowerType{
     Cannon,Ballista,Blaster
 } 
 
 impl TowerType {
-    pub fn get_tower(&self,assets:&GameAssets) -> (Handle<Scene>,Tower) {
+    pub const ALL:[TowerType;3] = [TowerType::Cannon,TowerType::Ballista,TowerType::Blaster];
+
+    //建造价格
+    pub fn cost(&self,tuning:&Tuning) -> u32 {
+        tuning.tower(*self).cost
+    }
+
+    //资源清单里的逻辑名,模型和建造菜单图标同名
+    pub fn asset_key(&self) -> &'static str {
         match self {
-            //加农炮
-            TowerType::Cannon => (
-                assets.weapon_cannon.clone(),
-                Tower{ shooting_timer: Timer::from_seconds(2.0, TimerMode::Repeating) }
-            ),
-            //投射机
-            TowerType::Ballista => (
-                assets.weapon_ballista.clone(),
-                Tower{ shooting_timer: Timer::from_seconds(1.0, TimerMode::Repeating) }
-            ),
-            //能量武器
-            TowerType::Blaster => (
-                assets.weapon_blaster.clone(),
-                Tower{ shooting_timer: Timer::from_seconds(0.25, TimerMode::Repeating) }
-            )
+            TowerType::Cannon => "weapon_cannon",
+            TowerType::Ballista => "weapon_ballista",
+            TowerType::Blaster => "weapon_blaster",
+        }
+    }
+
+    pub fn bullet_key(&self) -> &'static str {
+        match self {
+            TowerType::Cannon => "cannon_bullet",
+            TowerType::Ballista => "ballista_bullet",
+            TowerType::Blaster => "blaster_bullet",
+        }
+    }
+
+    pub fn sfx_key(&self) -> &'static str {
+        match self {
+            TowerType::Cannon => "cannon_fire",
+            TowerType::Ballista => "ballista_fire",
+            TowerType::Blaster => "blaster_fire",
+        }
+    }
+
+    pub fn get_tower(&self,assets:&GameAssets,tuning:&Tuning) -> (Handle<Scene>,Tower) {
+        (assets.scene(self.asset_key()),self.base_tower(tuning))
+    }
+
+    //一级塔的属性,数值在tuning.ron里
+    pub fn base_tower(&self,tuning:&Tuning) -> Tower {
+        let tuned = tuning.tower(*self);
+        Tower{
+            targets:tuned.targets,
+            ..Tower::new(tuned.interval, tuned.damage, tuned.range, tuned.cost)
+        }
+    }
+
+    //按当前数值重新算出某一级塔的属性
+    pub fn tower_at_tier(&self,tuning:&Tuning,tier:u32) -> Tower {
+        let mut tower = self.base_tower(tuning);
+        while tower.tier < tier {
+            let cost = tower.upgrade_cost(self, tuning);
+            tower.upgrade(cost);
         }
+        tower
     }
 
     pub fn get_bullet(&self,position:Vec3,bullet_dir:Vec3,time:&Time,assets:&GameAssets) -> (Handle<Scene>,Bullet) {
         match self {
             TowerType::Cannon => (
-                assets.cannon_bullet.clone(),
+                assets.scene(self.bullet_key()),
                 Bullet{ 
-                    new:position+bullet_dir*time.delta_seconds()*12.5,old:position,
+                    new:position+bullet_dir*time.raw_delta_seconds()*12.5,old:position,
                     gravity_scalar:0.098, friction_scalar:0.9998,
                     is_missile:false,
-                    damage:10
+                    damage:10,
+                    damage_type:DamageType::Kinetic,
+                    crit:false,
+                    source:None
                 }
             ),
             TowerType::Ballista => (
-                assets.ballista_bullet.clone(),
+                assets.scene(self.bullet_key()),
                 Bullet{ 
-                    new:position+bullet_dir*time.delta_seconds()*8.5,old:position,
+                    new:position+bullet_dir*time.raw_delta_seconds()*8.5,old:position,
                     gravity_scalar:0.0098, friction_scalar:1.0002,
                     is_missile:false,
-                    damage:1
+                    damage:1,
+                    damage_type:DamageType::Piercing,
+                    crit:false,
+                    source:None
                 }
             ),
             TowerType::Blaster => (
-                assets.blaster_bullet.clone(),
+                assets.scene(self.bullet_key()),
                 Bullet{ 
-                    new:position+bullet_dir*time.delta_seconds()*20.,old:position,
+                    new:position+bullet_dir*time.raw_delta_seconds()*20.,old:position,
                     gravity_scalar:0., friction_scalar:1.0,
                     is_missile:false,
-                    damage:1
+                    damage:1,
+                    damage_type:DamageType::Energy,
+                    crit:false,
+                    source:None
                 }
             ),
         }
     }
 
-    pub fn get_sfx(&self,assets:&GameAssets) -> Handle<AudioSource> {
+    //射速快的塔同时响的声音少一些
+    pub fn sfx_limit(&self) -> VoiceLimit {
         match self {
-            TowerType::Blaster => assets.blaster_fire_audio.clone(),
-            TowerType::Ballista => assets.ballista_fire_audio.clone(),
-            TowerType::Cannon => assets.cannon_fire_audio.clone(),
+            TowerType::Blaster => VoiceLimit{ cooldown: 0.08, max_voices: 3, length: 0.3 },
+            TowerType::Ballista => VoiceLimit{ cooldown: 0.1, max_voices: 3, length: 0.6 },
+            TowerType::Cannon => VoiceLimit{ cooldown: 0.1, max_voices: 4, length: 1.0 },
         }
     }
+
+    pub fn get_sfx(&self,assets:&GameAssets) -> Handle<AudioSource> {
+        assets.audio(self.sfx_key())
+    }
 }
 
 pub struct TowerPlugin;
@@ -87,68 +254,164 @@ impl Plugin for TowerPlugin {
     fn build(&self, app: &mut App) {
         app
         .register_type::<Tower>()
-        .add_system(tower_shooting);
+        .register_type::<TargetingMode>()
+        .register_type::<TargetLayers>()
+        .add_system_set(
+            SystemSet::on_update(GameState::Playing)
+            .with_system(tower_shooting)
+            .with_system(tower_fire_sound)
+            .with_system(tower_record_hits)
+            .with_system(tower_take_damage)
+            .with_system(tower_destroy.after(tower_take_damage))
+            .with_system(tower_hit_sound)
+        );
     }
 }
 
 fn tower_shooting(
-    mut commands:Commands,
     // mut meshes:ResMut<Assets<Mesh>>,
     // mut materials:ResMut<Assets<StandardMaterial>>,
     mut towers:Query<(Entity,&mut Tower,&TowerType,&GlobalTransform,&mut Transform)>,
-    mut bullet_collectors:Query<Entity,With<BulletCollector>>,
-    targets:Query<(&GlobalTransform,&Target),Without<TargetDeath>>,
+    mut fire:EventWriter<FireBullet>,
+    targets:Query<(&GlobalTransform,&Health,&PathFollower,&MovementLayer,Option<&Stealth>),(With<Target>,Without<TargetDeath>)>,
     time:Res<Time>,
     assets:Res<GameAssets>,
-    audio:Res<Audio>
+    mut fired:EventWriter<ProjectileFired>
 ){
-    if targets.iter().count() < 1 {
-        return;
-    }
     for (e,mut tower,tower_type,global_tansform,mut local_transform) in towers.iter_mut() {
-        tower.shooting_timer.tick(time.delta());
-        if tower.shooting_timer.just_finished() {
+        //装填好之后停止计时,没有目标时不浪费这一发
+        if !tower.loaded {
+            tower.shooting_timer.tick(time.delta());
+            tower.loaded = tower.shooting_timer.just_finished();
+        }
+        if tower.loaded {
             //bullet
             let spawn_offset = Vec3::new(0.,0.25,0.);
             let bullet_spawn_position = global_tansform.translation() + spawn_offset;
             let forward = -Vec3::Z;
 
-            if let Some((closest_target,_)) = targets.iter().min_by_key(|(target_transform,_)|{
-                FloatOrd(target_transform.translation().distance(bullet_spawn_position))
-            }) {
-                let pos = closest_target.translation() - bullet_spawn_position;
-                let target_pos = pos+Vec3::Y*0.1+Vec3::X*0.1;
-                local_transform.look_at(target_pos, Vec3::Y);
-            }
+            //射程按地面上的水平距离算,和射程圈一致,飞得高的敌人也打得到
+            let distance = |t:&GlobalTransform| ((t.translation() - bullet_spawn_position) * Vec3::new(1.,0.,1.)).length();
+            let in_range = targets.iter()
+            .filter(|(t,..)| distance(t) <= tower.range)
+            .filter(|(_,_,_,layer,_)| tower.targets.can_hit(**layer))
+            .filter(|(..,stealth)| !stealth.map_or(false,|stealth| stealth.hidden()));
+            let target = match tower.targeting {
+                TargetingMode::First => in_range.max_by_key(|(t,_,follower,..)|{
+                    let left = follower.waypoints.get(follower.next)
+                    .map(|w| (t.translation() * Vec3::new(1.,0.,1.)).distance(*w))
+                    .unwrap_or_default();
+                    (follower.next,FloatOrd(-left))
+                }),
+                TargetingMode::Closest => in_range.min_by_key(|(t,..)| FloatOrd(distance(t))),
+                TargetingMode::Strongest => in_range.max_by_key(|(_,health,..)| health.value),
+                TargetingMode::Weakest => in_range.min_by_key(|(_,health,..)| health.value),
+            };
+            let target = match target {
+                Some((target,..)) => target,
+                None => continue
+            };
+            tower.loaded = false;
 
-            let (bullet_model,bullet) = tower_type.get_bullet(bullet_spawn_position,local_transform.rotation * forward,&time,&assets);
-
-            // commands.entity(e).with_children(|cb|{
-                
-            // });
-
-            match bullet_collectors.get_single_mut() {
-                Ok(collector) => {
-                    commands.entity(collector).with_children(|cb|{
-                        cb.spawn(SceneBundle{
-                            scene:bullet_model.clone(),
-                            transform:Transform { 
-                                translation: bullet_spawn_position, 
-                                scale: Vec3::new(0.05,0.05,0.05),
-                                rotation: local_transform.rotation
-                            },
-                            ..default()
-                        })
-                        .insert(Lifetime{timer:Timer::from_seconds(5., TimerMode::Once)})
-                        .insert(bullet)
-                        .insert(Name::new("Bullet"));
-                    });
-                },
-                Err(_) => todo!(),
+            let pos = target.translation() - bullet_spawn_position;
+            let target_pos = pos+Vec3::Y*0.1+Vec3::X*0.1;
+            local_transform.look_at(target_pos, Vec3::Y);
+
+            let (bullet_model,mut bullet) = tower_type.get_bullet(bullet_spawn_position,local_transform.rotation * forward,&time,&assets);
+            bullet.crit = random::<f32>() < CRIT_CHANCE;
+            bullet.damage = if bullet.crit { tower.damage * CRIT_MULTIPLIER } else { tower.damage };
+            bullet.source = Some(e);
+            let crit = bullet.crit;
+
+            //子弹从对象池里取
+            fire.send(FireBullet{
+                model:*tower_type,
+                scene:bullet_model,
+                bullet,
+                transform:Transform { 
+                    translation: bullet_spawn_position, 
+                    scale: Vec3::new(0.05,0.05,0.05),
+                    rotation: local_transform.rotation
+                }
+            });
+            fired.send(ProjectileFired{
+                tower:e,
+                tower_type:*tower_type,
+                position:bullet_spawn_position,
+                crit
+            });
+        }
+    }
+}
+
+fn tower_fire_sound(
+    mut fired:EventReader<ProjectileFired>,
+    mut sounds:EventWriter<PlaySound>,
+    assets:Res<GameAssets>
+){
+    for event in fired.iter() {
+        sounds.send(PlaySound::sfx(event.tower_type.get_sfx(&assets)).with_limit(event.tower_type.sfx_limit()));
+    }
+}
+
+//塔的伤害和击杀统计
+fn tower_record_hits(
+    mut hits:EventReader<EnemyDamaged>,
+    mut towers:Query<&mut Tower>
+){
+    for hit in hits.iter() {
+        if let Some(mut tower) = hit.source.and_then(|e| towers.get_mut(e).ok()) {
+            tower.damage_dealt += hit.damage;
+            if hit.lethal {
+                tower.kills += 1;
             }
-            
-            // audio.play(assets.cannon_fire_audio.clone());
-            audio.play_with_settings(tower_type.get_sfx(&assets), PlaybackSettings { repeat: false, volume: 0.4, speed: 1.0 });
         }
     }
-}
\ No newline at end of file
+}
+
+fn tower_take_damage(
+    mut damaged:EventReader<TowerDamaged>,
+    mut towers:Query<&mut Health,(With<Tower>,Without<Target>)>
+){
+    for event in damaged.iter() {
+        if let Ok(mut health) = towers.get_mut(event.tower) {
+            health.value -= event.damage;
+        }
+    }
+}
+
+//耐久打光的塔被摧毁,建在塔位上的恢复成空塔位
+fn tower_destroy(
+    mut commands:Commands,
+    mut materials:ResMut<Assets<StandardMaterial>>,
+    mut destroyed:EventWriter<TowerDestroyed>,
+    towers:Query<(&Health,&TowerType,&Parent),(With<Tower>,Without<Target>)>,
+    sites:Query<(&TowerSite,&Transform)>,
+    assets:Res<GameAssets>
+){
+    for (health,tower_type,parent) in towers.iter() {
+        if health.value > 0 {
+            continue;
+        }
+        let site = parent.get();
+        let (tower_site,transform) = match sites.get(site) {
+            Ok(site) => site,
+            Err(_) => continue
+        };
+        commands.entity(site).despawn_recursive();
+        if let Some(rotation) = tower_site.slot {
+            spawn_tower_slot(&mut commands, &mut materials, &assets, transform.translation, rotation);
+        }
+        destroyed.send(TowerDestroyed{ site, tower_type: *tower_type, position: transform.translation });
+    }
+}
+
+fn tower_hit_sound(
+    mut damaged:EventReader<TowerDamaged>,
+    mut sounds:EventWriter<PlaySound>,
+    assets:Res<GameAssets>
+){
+    for _ in damaged.iter() {
+        sounds.send(PlaySound::sfx(assets.audio("enemy_hit")).with_volume(0.7));
+    }
+}
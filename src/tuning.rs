@@ -0,0 +1,430 @@
+use std::time::Duration;
+use bevy::asset::{AssetLoader, LoadContext, LoadedAsset, BoxedFuture, LoadState};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::utils::HashMap;
+use serde::Deserialize;
+
+use crate::abilities::*;
+use crate::assets::*;
+use crate::boss::*;
+use crate::target::*;
+use crate::tower::*;
+
+pub const TUNING_PATH:&str = "tuning.ron";
+
+#[derive(Deserialize,Clone,Copy,Debug)]
+pub struct TowerTuning{
+    pub cost:u32,
+    pub interval:f32,
+    pub damage:i32,
+    pub range:f32,
+    //能打的目标,不写就是空中地面都打
+    #[serde(default)]
+    pub targets:TargetLayers,
+    //被敌人攻击时的耐久
+    pub health:i32,
+}
+
+#[derive(Deserialize,Clone,Copy,Debug)]
+pub struct EnemyTuning{
+    pub health:i32,
+    pub speed:f32,
+    pub speed_variation:f32,
+    pub bounty:u32,
+    pub score:u32,
+    pub layer:MovementLayer,
+    //飞行高度,地面单位贴地
+    pub altitude:f32,
+}
+
+#[derive(Deserialize,Clone,Copy,Debug)]
+pub struct WaveTuning{
+    pub delay_scale:f32,
+    pub interval_scale:f32,
+}
+
+impl Default for WaveTuning {
+    fn default() -> Self {
+        WaveTuning { delay_scale: 1.0, interval_scale: 1.0 }
+    }
+}
+
+//数值配置 assets/tuning.ron,支持热重载
+#[derive(Resource,TypeUuid,Deserialize,Clone,Debug)]
+#[uuid = "0f6a3c1d-7b2e-4d8a-9c5f-2e1b7a4d6c93"]
+#[serde(default)]
+pub struct Tuning{
+    pub towers:HashMap<TowerType,TowerTuning>,
+    pub enemies:HashMap<EnemyType,EnemyTuning>,
+    pub waves:WaveTuning,
+    //在这里出现的敌人类型是首领
+    pub bosses:HashMap<EnemyType,BossTuning>,
+    //每种敌人带的能力
+    pub abilities:HashMap<EnemyType,Vec<EnemyAbility>>,
+}
+
+//文件没加载好时用的数值
+impl Default for Tuning {
+    fn default() -> Self {
+        let towers = [
+            (TowerType::Cannon,TowerTuning{ cost: 50, interval: 2.0, damage: 10, range: 3.0, targets: TargetLayers::Ground, health: 60 }),
+            (TowerType::Ballista,TowerTuning{ cost: 30, interval: 1.0, damage: 1, range: 4.0, targets: TargetLayers::Air, health: 40 }),
+            (TowerType::Blaster,TowerTuning{ cost: 40, interval: 0.25, damage: 1, range: 2.5, targets: TargetLayers::Both, health: 50 }),
+        ];
+        let enemies = [
+            (EnemyType::Red,EnemyTuning{ health: 10, speed: 1.0, speed_variation: 1.0, bounty: 5, score: 10, layer: MovementLayer::Air, altitude: 1.0 }),
+            (EnemyType::Crawler,EnemyTuning{ health: 20, speed: 0.7, speed_variation: 0.3, bounty: 6, score: 12, layer: MovementLayer::Ground, altitude: 0.25 }),
+            (EnemyType::Mothership,EnemyTuning{ health: 300, speed: 0.4, speed_variation: 0.0, bounty: 60, score: 200, layer: MovementLayer::Air, altitude: 1.5 }),
+            (EnemyType::Phantom,EnemyTuning{ health: 8, speed: 1.3, speed_variation: 0.4, bounty: 8, score: 15, layer: MovementLayer::Air, altitude: 1.2 }),
+            (EnemyType::Medic,EnemyTuning{ health: 25, speed: 0.6, speed_variation: 0.2, bounty: 10, score: 20, layer: MovementLayer::Ground, altitude: 0.25 }),
+        ];
+        let abilities = [
+            (EnemyType::Crawler,vec![
+                EnemyAbility::Shield{ amount: 10, regen: 5.0, delay: 2.0 },
+                EnemyAbility::Rammer{ radius: 0.3, aggro: 3.0, damage: 25 },
+            ]),
+            (EnemyType::Phantom,vec![EnemyAbility::Stealth{ reveal_radius: 1.5 }]),
+            (EnemyType::Medic,vec![
+                EnemyAbility::Healer{ radius: 1.5, amount: 3, interval: 1.0 },
+                EnemyAbility::Splitter{ enemy: EnemyType::Crawler, count: 2 },
+            ]),
+            (EnemyType::Mothership,vec![EnemyAbility::Shooter{ range: 2.5, damage: 8, interval: 1.5 }]),
+        ];
+        let bosses = [
+            (EnemyType::Mothership,BossTuning{
+                name: "Mothership".to_string(),
+                scale: 1.2,
+                leak_lives: 5,
+                phases: vec![
+                    BossPhase{ health: 0.75, ability: BossAbility::Shield{ amount: 40, duration: 4.0 } },
+                    BossPhase{ health: 0.5, ability: BossAbility::SpeedUp(1.5) },
+                    BossPhase{ health: 0.25, ability: BossAbility::SpawnMinions{ enemy: EnemyType::Red, count: 4 } },
+                ]
+            }),
+        ];
+        Tuning {
+            towers: towers.into_iter().collect(),
+            enemies: enemies.into_iter().collect(),
+            waves: WaveTuning::default(),
+            bosses: bosses.into_iter().collect(),
+            abilities: abilities.into_iter().collect()
+        }
+    }
+}
+
+impl Tuning {
+    //文件里漏写的类型用默认值
+    pub fn tower(&self,tower_type:TowerType) -> TowerTuning {
+        self.towers.get(&tower_type).copied()
+        .unwrap_or_else(|| Tuning::default().towers[&tower_type])
+    }
+
+    pub fn enemy(&self,enemy_type:EnemyType) -> EnemyTuning {
+        self.enemies.get(&enemy_type).copied()
+        .unwrap_or_else(|| Tuning::default().enemies[&enemy_type])
+    }
+
+    pub fn boss(&self,enemy_type:EnemyType) -> Option<&BossTuning> {
+        self.bosses.get(&enemy_type)
+    }
+
+    //解析并检查数值,加载器和出错时的提示都走这里
+    pub fn parse(bytes:&[u8]) -> Result<Tuning,String> {
+        let tuning = ron::de::from_bytes::<Tuning>(bytes).map_err(|e| e.to_string())?;
+        tuning.validate()?;
+        Ok(tuning)
+    }
+
+    //计时器的时长不能是负数或NaN,重复计时器还不能是0
+    pub fn validate(&self) -> Result<(),String> {
+        fn positive(name:String,value:f32) -> Result<(),String> {
+            if value.is_finite() && value > 0. { Ok(()) } else { Err(format!("{} must be positive, got {}",name,value)) }
+        }
+        fn non_negative(name:String,value:f32) -> Result<(),String> {
+            if value.is_finite() && value >= 0. { Ok(()) } else { Err(format!("{} must not be negative, got {}",name,value)) }
+        }
+        //血量为0时塔和敌人一出来就没了
+        fn alive(name:String,value:i32) -> Result<(),String> {
+            if value > 0 { Ok(()) } else { Err(format!("{} must be positive, got {}",name,value)) }
+        }
+        fn amount(name:String,value:i32) -> Result<(),String> {
+            if value >= 0 { Ok(()) } else { Err(format!("{} must not be negative, got {}",name,value)) }
+        }
+        for (tower_type,tower) in self.towers.iter() {
+            positive(format!("towers.{:?}.interval",tower_type),tower.interval)?;
+            non_negative(format!("towers.{:?}.range",tower_type),tower.range)?;
+            amount(format!("towers.{:?}.damage",tower_type),tower.damage)?;
+            alive(format!("towers.{:?}.health",tower_type),tower.health)?;
+        }
+        for (enemy_type,enemy) in self.enemies.iter() {
+            alive(format!("enemies.{:?}.health",enemy_type),enemy.health)?;
+            non_negative(format!("enemies.{:?}.speed",enemy_type),enemy.speed)?;
+            non_negative(format!("enemies.{:?}.speed_variation",enemy_type),enemy.speed_variation)?;
+            non_negative(format!("enemies.{:?}.altitude",enemy_type),enemy.altitude)?;
+        }
+        non_negative("waves.delay_scale".to_string(),self.waves.delay_scale)?;
+        positive("waves.interval_scale".to_string(),self.waves.interval_scale)?;
+        for (enemy_type,boss) in self.bosses.iter() {
+            positive(format!("bosses.{:?}.scale",enemy_type),boss.scale)?;
+            for phase in boss.phases.iter() {
+                if !(phase.health.is_finite() && phase.health > 0. && phase.health <= 1.) {
+                    return Err(format!("bosses.{:?} phase health must be between 0 and 1, got {}",enemy_type,phase.health));
+                }
+                match phase.ability {
+                    BossAbility::SpeedUp(multiplier) => positive(format!("bosses.{:?} SpeedUp",enemy_type),multiplier)?,
+                    BossAbility::Shield { amount: shield, duration } => {
+                        amount(format!("bosses.{:?} Shield.amount",enemy_type),shield)?;
+                        non_negative(format!("bosses.{:?} Shield.duration",enemy_type),duration)?;
+                    },
+                    BossAbility::SpawnMinions { .. } => {},
+                }
+            }
+        }
+        for (enemy_type,abilities) in self.abilities.iter() {
+            for ability in abilities.iter() {
+                match *ability {
+                    EnemyAbility::Shield { amount: shield, regen, delay } => {
+                        amount(format!("abilities.{:?} Shield.amount",enemy_type),shield)?;
+                        non_negative(format!("abilities.{:?} Shield.regen",enemy_type),regen)?;
+                        non_negative(format!("abilities.{:?} Shield.delay",enemy_type),delay)?;
+                    },
+                    EnemyAbility::Healer { radius, amount: heal, interval } => {
+                        amount(format!("abilities.{:?} Healer.amount",enemy_type),heal)?;
+                        non_negative(format!("abilities.{:?} Healer.radius",enemy_type),radius)?;
+                        positive(format!("abilities.{:?} Healer.interval",enemy_type),interval)?;
+                    },
+                    EnemyAbility::Shooter { range, damage, interval } => {
+                        amount(format!("abilities.{:?} Shooter.damage",enemy_type),damage)?;
+                        non_negative(format!("abilities.{:?} Shooter.range",enemy_type),range)?;
+                        positive(format!("abilities.{:?} Shooter.interval",enemy_type),interval)?;
+                    },
+                    EnemyAbility::Stealth { reveal_radius } => non_negative(format!("abilities.{:?} Stealth.reveal_radius",enemy_type),reveal_radius)?,
+                    EnemyAbility::Rammer { radius, aggro, damage } => {
+                        amount(format!("abilities.{:?} Rammer.damage",enemy_type),damage)?;
+                        positive(format!("abilities.{:?} Rammer.radius",enemy_type),radius)?;
+                        non_negative(format!("abilities.{:?} Rammer.aggro",enemy_type),aggro)?;
+                    },
+                    EnemyAbility::Splitter { .. } => {},
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn abilities(&self,enemy_type:EnemyType) -> &[EnemyAbility] {
+        self.abilities.get(&enemy_type).map(|abilities| abilities.as_slice()).unwrap_or_default()
+    }
+
+    //漏到终点扣的生命
+    pub fn lives_lost(&self,enemy_type:EnemyType) -> u32 {
+        self.boss(enemy_type).map(|boss| boss.leak_lives).unwrap_or(1)
+    }
+}
+
+#[derive(Default)]
+pub struct TuningLoader;
+
+impl AssetLoader for TuningLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let tuning = Tuning::parse(bytes).map_err(bevy::asset::Error::msg)?;
+            load_context.set_default_asset(LoadedAsset::new(tuning));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["tuning.ron"]
+    }
+}
+
+#[derive(Resource)]
+pub struct TuningFile{
+    pub handle:Handle<Tuning>,
+    //最近一次解析失败的原因
+    pub error:Option<String>,
+}
+
+#[derive(Component)]
+pub struct TuningErrorOverlay;
+
+pub struct TuningPlugin;
+
+impl Plugin for TuningPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .add_asset::<Tuning>()
+        .init_asset_loader::<TuningLoader>()
+        .init_resource::<Tuning>()
+        .add_startup_system(load_tuning)
+        .add_system(tuning_reload)
+        .add_system(tuning_error_overlay.after(tuning_reload))
+        .add_system(tuning_apply.after(tuning_reload))
+        .add_system(tuning_apply_abilities.after(tuning_reload));
+    }
+}
+
+fn load_tuning(
+    mut commands:Commands,
+    asset:Res<AssetServer>
+){
+    commands.insert_resource(TuningFile{
+        handle:asset.load(TUNING_PATH),
+        error:None
+    });
+}
+
+fn tuning_reload(
+    mut events:EventReader<AssetEvent<Tuning>>,
+    mut file:ResMut<TuningFile>,
+    mut tuning:ResMut<Tuning>,
+    tunings:Res<Assets<Tuning>>,
+    asset_server:Res<AssetServer>
+){
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } if *handle == file.handle => {},
+            _ => continue
+        }
+        if let Some(loaded) = tunings.get(&file.handle) {
+            *tuning = loaded.clone();
+            file.error = None;
+            info!("tuning reloaded");
+        }
+    }
+
+    //解析或检查失败时保留旧数值,自己再解析一次拿到错误信息
+    if file.error.is_none() && asset_server.get_load_state(&file.handle) == LoadState::Failed {
+        let path = format!("assets/{}",TUNING_PATH);
+        let error = match std::fs::read(&path) {
+            Ok(bytes) => Tuning::parse(&bytes).err().unwrap_or_else(|| "unknown error".to_string()),
+            Err(e) => e.to_string()
+        };
+        error!("{}: {}",path,error);
+        file.error = Some(format!("{}: {}",path,error));
+    }
+}
+
+fn tuning_error_overlay(
+    mut commands:Commands,
+    file:Res<TuningFile>,
+    assets:Res<GameAssets>,
+    overlays:Query<Entity,With<TuningErrorOverlay>>
+){
+    if !file.is_changed() {
+        return;
+    }
+    for e in overlays.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+    if let Some(error) = file.error.as_ref() {
+        commands.spawn(TextBundle::from_section(
+            format!("Tuning reload failed, keeping old values\n{}",error),
+            TextStyle { font: assets.font("ui"), font_size: 18., color: Color::rgb(1.0,0.5,0.4) }
+        ).with_style(Style{
+            position_type:PositionType::Absolute,
+            position:UiRect{ bottom:Val::Px(10.), left:Val::Px(10.), ..default() },
+            padding:UiRect::all(Val::Px(8.)),
+            ..default()
+        }))
+        .insert(BackgroundColor(Color::rgba(0.,0.,0.,0.7)))
+        .insert(TuningErrorOverlay)
+        .insert(Name::new("TuningError"));
+    }
+}
+
+//新数值作用到场上已有的塔和敌人
+fn tuning_apply(
+    tuning:Res<Tuning>,
+    mut towers:Query<(&mut Tower,&mut Health,&TowerType),Without<Target>>,
+    mut targets:Query<(&mut Target,&mut Health,&mut Flight,&EnemyType,Option<&Boss>)>
+){
+    if !tuning.is_changed() || tuning.is_added() {
+        return;
+    }
+    for (mut tower,mut health,tower_type) in towers.iter_mut() {
+        let tuned = tower_type.tower_at_tier(&tuning, tower.tier);
+        tower.shooting_timer.set_duration(tuned.shooting_timer.duration());
+        tower.damage = tuned.damage;
+        tower.range = tuned.range;
+        tower.targets = tuned.targets;
+        health.rescale(tuning.tower(*tower_type).health);
+    }
+    for (mut target,mut health,mut flight,enemy_type,boss) in targets.iter_mut() {
+        let tuned = tuning.enemy(*enemy_type);
+        //首领加速阶段的倍数保留
+        let speed_scale = boss.map(|boss| boss.speed_scale).unwrap_or(1.);
+        target.speed = (tuned.speed + target.speed_roll * tuned.speed_variation) * speed_scale;
+        flight.altitude = tuned.altitude;
+        health.rescale(tuned.health);
+    }
+}
+
+//已有能力的数值也跟着更新;新加或删掉的能力只影响之后出生的敌人
+fn tuning_apply_abilities(
+    tuning:Res<Tuning>,
+    mut enemies:Query<(
+        &EnemyType,
+        Option<&mut Shield>,
+        Option<&mut ShieldRegen>,
+        Option<&mut Healer>,
+        Option<&mut Stealth>,
+        Option<&mut TowerAttacker>,
+        Option<&mut Rammer>,
+        Option<&BossShield>
+    ),With<Target>>
+){
+    if !tuning.is_changed() || tuning.is_added() {
+        return;
+    }
+    for (enemy_type,mut shield,mut regen,mut healer,mut stealth,mut attacker,mut rammer,boss_shield) in enemies.iter_mut() {
+        for ability in tuning.abilities(*enemy_type) {
+            match *ability {
+                EnemyAbility::Shield { amount, regen: rate, delay } => {
+                    if let Some(shield) = shield.as_mut() {
+                        //首领阶段叠加的部分保留
+                        let max = amount + boss_shield.map(|b| b.amount).unwrap_or_default();
+                        shield.max = max;
+                        shield.value = shield.value.min(max);
+                    }
+                    if let Some(regen) = regen.as_mut() {
+                        regen.rate = rate;
+                        regen.delay = delay;
+                    }
+                },
+                EnemyAbility::Healer { radius, amount, interval } => {
+                    if let Some(healer) = healer.as_mut() {
+                        healer.radius = radius;
+                        healer.amount = amount;
+                        healer.timer.set_duration(Duration::from_secs_f32(interval));
+                    }
+                },
+                EnemyAbility::Stealth { reveal_radius } => {
+                    if let Some(stealth) = stealth.as_mut() {
+                        stealth.reveal_radius = reveal_radius;
+                    }
+                },
+                EnemyAbility::Shooter { range, damage, interval } => {
+                    if let Some(attacker) = attacker.as_mut() {
+                        attacker.range = range;
+                        attacker.damage = damage;
+                        attacker.timer.set_duration(Duration::from_secs_f32(interval));
+                    }
+                },
+                EnemyAbility::Rammer { radius, aggro, damage } => {
+                    if let Some(rammer) = rammer.as_mut() {
+                        rammer.radius = radius;
+                        rammer.aggro = aggro;
+                        rammer.damage = damage;
+                    }
+                },
+                EnemyAbility::Splitter { .. } => {},
+            }
+        }
+    }
+}
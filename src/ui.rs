@@ -1,31 +1,142 @@
 use bevy::{prelude::*, ecs::query::QuerySingleError};
 use bevy_mod_picking::*;
 
-use crate::{GameAssets, TowerType};
+use bevy::pbr::NotShadowCaster;
+
+use crate::{GameAssets, TowerType, Tower, LevelEntity, LevelEditor, PlayerStats, NavGrid, NavObstacle, GameSpeed, WaveState};
+use crate::{TowerSlot, spawn_tower_slot, MAX_TIER, Action, PlayerInput, Tuning};
+use crate::{TowerBuilt, TowerUpgraded, TowerSold, TowerRepaired, Health, repair_cost, GameState};
+use leafwing_input_manager::prelude::*;
 
 pub struct GameUIPlugin;
 
 impl Plugin for GameUIPlugin {
     fn build(&self, app: &mut App) {
         app
+        .init_resource::<BuildMenuChoice>()
         // .add_startup_system(create_ui)
-        .add_system(tower_button_clicked)
-        .add_system(create_ui_on_selected);
+        .add_startup_system(create_speed_label)
+        .add_startup_system(create_hud)
+        .add_system_set(
+            SystemSet::on_update(GameState::Playing)
+            .with_system(update_speed_label)
+            .with_system(update_hud)
+            .with_system(tower_button_clicked)
+            .with_system(create_ui_on_selected)
+            .with_system(create_info_on_selected)
+            .with_system(update_tower_info)
+            .with_system(tower_action_clicked)
+        );
     }
 }
 
 #[derive(Component)]
 pub struct TowerRootUI;
 
+//建造菜单里塔的顺序
+pub const BUILD_MENU:[TowerType;3] = [TowerType::Ballista,TowerType::Blaster,TowerType::Cannon];
+
+//手柄在建造菜单里选中的塔
+#[derive(Resource,Default)]
+pub struct BuildMenuChoice(pub usize);
+
+#[derive(Component)]
+pub struct SpeedLabel;
+
+fn create_speed_label(
+    mut commands:Commands,
+    assets:Res<GameAssets>
+){
+    commands.spawn(TextBundle::from_section(
+        "1x",
+        TextStyle { font: assets.font("ui"), font_size: 24., color: Color::WHITE }
+    ).with_style(Style{
+        position_type:PositionType::Absolute,
+        position:UiRect{ top:Val::Px(10.), right:Val::Px(10.), ..default() },
+        ..default()
+    }))
+    .insert(SpeedLabel)
+    .insert(Name::new("SpeedLabel"));
+}
+
+fn update_speed_label(
+    speed:Res<GameSpeed>,
+    mut labels:Query<&mut Text,With<SpeedLabel>>
+){
+    if !speed.is_changed() {
+        return;
+    }
+    for mut text in labels.iter_mut() {
+        text.sections[0].value = speed.label();
+    }
+}
+
+#[derive(Component,Clone,Copy)]
+pub enum HudField{
+    Gold,Lives,Wave,NextWave,Enemies,Score
+}
+
+fn create_hud(
+    mut commands:Commands,
+    assets:Res<GameAssets>
+){
+    let style = TextStyle { font: assets.font("ui"), font_size: 20., color: Color::WHITE };
+    let fields = [HudField::Gold,HudField::Lives,HudField::Wave,HudField::NextWave,HudField::Enemies,HudField::Score];
+
+    commands.spawn(NodeBundle{
+        style:Style{
+            position_type:PositionType::Absolute,
+            position:UiRect{ top:Val::Px(10.), left:Val::Px(10.), ..default() },
+            flex_direction:FlexDirection::Column,
+            padding:UiRect::all(Val::Px(8.)),
+            ..default()
+        },
+        background_color:Color::rgba(0.,0.,0.,0.4).into(),
+        ..default()
+    })
+    .insert(Name::new("HUD"))
+    .with_children(|cb|{
+        for field in fields {
+            cb.spawn(TextBundle::from_section("", style.clone()))
+            .insert(field);
+        }
+    });
+}
+
+//只读资源,不遍历敌人
+fn update_hud(
+    stats:Res<PlayerStats>,
+    waves:Res<WaveState>,
+    mut texts:Query<(&mut Text,&HudField)>
+){
+    if !stats.is_changed() && !waves.is_changed() {
+        return;
+    }
+    for (mut text,field) in texts.iter_mut() {
+        text.sections[0].value = match field {
+            HudField::Gold => format!("Gold: {}",stats.gold),
+            HudField::Lives => format!("Lives: {}",stats.lives),
+            HudField::Wave => format!("Wave: {}/{}",waves.current,waves.waves.len()),
+            HudField::NextWave => if waves.finished() {
+                "Next: -".to_string()
+            } else if waves.bosses > 0 {
+                "Next: defeat the boss".to_string()
+            } else if waves.spawning {
+                format!("Next: wave {} after this one",waves.current + 1)
+            } else {
+                format!("Next: wave {} in {:.0}s",waves.current + 1,waves.timer.remaining_secs().ceil())
+            },
+            HudField::Enemies => format!("Enemies left: {}",waves.remaining),
+            HudField::Score => format!("Score: {}",stats.score),
+        };
+    }
+}
+
 fn create_ui(
     mut commands:Commands,
     assets:Res<GameAssets>
 ){
-    let types = [
-        (assets.weapon_ballista_img.clone(),TowerType::Ballista),
-        (assets.weapon_blaster_img.clone(),TowerType::Blaster),
-        (assets.weapon_cannon_img.clone(),TowerType::Cannon)
-    ];
+    let types = BUILD_MENU.map(|tower_type| (assets.image(tower_type.asset_key()),tower_type));
 
     commands.spawn(NodeBundle{
         style:Style { 
@@ -57,35 +168,67 @@ fn create_ui(
 
 fn tower_button_clicked(
     mut commands:Commands,
-    selection:Query<(Entity,&Selection,&Transform)>,
+    mut materials:ResMut<Assets<StandardMaterial>>,
+    mut stats:ResMut<PlayerStats>,
+    nav:Res<NavGrid>,
+    selection:Query<(Entity,&Selection,&Transform),With<TowerSlot>>,
     assets:Res<GameAssets>,
-    query:Query<(&Interaction,&TowerType),Changed<Interaction>>
+    tuning:Res<Tuning>,
+    choice:Res<BuildMenuChoice>,
+    query:Query<(&Interaction,&TowerType),Changed<Interaction>>,
+    input:Query<&ActionState<Action>,With<PlayerInput>>,
+    mut built:EventWriter<TowerBuilt>
 ){
-    for (interaction,tower_type) in query.iter() {
-        if matches!(interaction,Interaction::Clicked) {
-            info!("spawn tower!");
-            for (e,selection,transform) in selection.iter() {
-                if selection.selected() {
-                    commands.entity(e).despawn_recursive();
-                    spawn_tower(&mut commands, &assets, tower_type.clone(),transform.translation,transform.rotation);
+    let clicked = query.iter()
+    .filter(|(interaction,_)| matches!(interaction,Interaction::Clicked))
+    .map(|(_,tower_type)| *tower_type);
+    //手柄确认建造菜单里选中的塔
+    let confirmed = input.iter()
+    .filter(|input| input.just_pressed(Action::Confirm))
+    .map(|_| BUILD_MENU[choice.0 % BUILD_MENU.len()]);
+    for tower_type in clicked.chain(confirmed) {
+        for (e,selection,transform) in selection.iter() {
+            if selection.selected() {
+                if stats.gold < tower_type.cost(&tuning) {
+                    info!("not enough gold!");
+                    continue;
+                }
+                if nav.blocks_route(transform.translation) {
+                    info!("tower would block the route!");
+                    continue;
                 }
+                let cost = tower_type.cost(&tuning);
+                stats.gold -= cost;
+                commands.entity(e).despawn_recursive();
+                let site = spawn_tower(&mut commands, &mut materials, &assets, &tuning, tower_type,transform.translation,Some(transform.rotation));
+                built.send(TowerBuilt{ site, tower_type, position: transform.translation, cost });
             }
         }
     }
 }
 
-fn spawn_tower(
+//建好的塔,slot是原来塔位的朝向,卖掉或被摧毁后按原样恢复塔位;随意放置的塔没有
+#[derive(Component)]
+pub struct TowerSite{
+    pub slot:Option<Quat>
+}
+
+pub fn spawn_tower(
     commands:&mut Commands,
+    materials:&mut Assets<StandardMaterial>,
     assets:&GameAssets,
+    tuning:&Tuning,
     tower_type:TowerType,
     position:Vec3,
-    rotation:Quat,
+    slot:Option<Quat>,
 ) -> Entity {
 
-    let (tower_model,tower) = tower_type.get_tower(assets);
+    let (tower_model,tower) = tower_type.get_tower(assets,tuning);
+    let default_color = materials.add(Color::rgba(0.3,0.5, 0.3, 0.0).into());
+    let selected_color = materials.add(Color::rgba(0.3,0.9, 0.3, 0.6).into());
 
     commands.spawn(SceneBundle{
-        scene: assets.tower_base.clone(),
+        scene: assets.scene("tower_base"),
         transform: Transform{
             translation: position, 
             rotation: Quat::IDENTITY, 
@@ -93,6 +236,19 @@ fn spawn_tower(
         },
         ..default()
     })
+    .insert(assets.mesh("tower_base"))
+    .insert(default_color.clone())
+    .insert(Highlighting{
+        initial: default_color.clone(),
+        hovered: Some(selected_color.clone()),
+        pressed: Some(selected_color.clone()),
+        selected: Some(selected_color.clone()),
+    })
+    .insert(NotShadowCaster)
+    .insert(PickableBundle::default())
+    .insert(TowerSite{slot})
+    .insert(NavObstacle)
+    .insert(LevelEntity)
     .insert(Name::new("TowerBase"))
     .with_children(|cb|{
         cb.spawn(SceneBundle{
@@ -106,6 +262,7 @@ fn spawn_tower(
         })
         .insert(tower)
         .insert(tower_type)
+        .insert(Health::new(tuning.tower(tower_type).health))
         .insert(Name::new("Tower"));
     })
     .id()
@@ -114,10 +271,12 @@ fn spawn_tower(
 fn create_ui_on_selected(
     mut commands:Commands,
     assets:Res<GameAssets>,
-    selection:Query<&Selection>,
+    editor:Res<LevelEditor>,
+    selection:Query<&Selection,With<TowerSlot>>,
     root:Query<Entity,With<TowerRootUI>>
 ){
-    let any_selection = selection.iter().any(|s|{s.selected()});
+    //编辑关卡时不显示建造菜单
+    let any_selection = !editor.enabled && selection.iter().any(|s|{s.selected()});
     match root.get_single() {
         Ok(e) => {
             if !any_selection {
@@ -134,4 +293,200 @@ fn create_ui_on_selected(
             error!("too many ui roots!")
         }
     }
-}
\ No newline at end of file
+}
+
+#[derive(Component)]
+pub struct TowerInfoUI;
+
+#[derive(Component)]
+pub struct TowerInfoText;
+
+#[derive(Component,Clone,Copy)]
+pub enum TowerAction{
+    Upgrade,Sell,Priority,Repair
+}
+
+fn create_info_ui(
+    mut commands:Commands,
+    assets:Res<GameAssets>
+){
+    let style = TextStyle { font: assets.font("ui"), font_size: 18., color: Color::WHITE };
+    let actions = [
+        (TowerAction::Upgrade,"Upgrade"),
+        (TowerAction::Sell,"Sell"),
+        (TowerAction::Priority,"Priority"),
+        (TowerAction::Repair,"Repair")
+    ];
+
+    commands.spawn(NodeBundle{
+        style:Style{
+            position_type:PositionType::Absolute,
+            position:UiRect{ top:Val::Px(50.), right:Val::Px(10.), ..default() },
+            flex_direction:FlexDirection::Column,
+            padding:UiRect::all(Val::Px(8.)),
+            ..default()
+        },
+        background_color:Color::rgba(0.,0.,0.,0.6).into(),
+        ..default()
+    })
+    .insert(TowerInfoUI)
+    .insert(Name::new("TowerInfo"))
+    .with_children(|cb|{
+        cb.spawn(TextBundle::from_section("", style.clone()))
+        .insert(TowerInfoText);
+        for (action,label) in actions {
+            cb.spawn(ButtonBundle{
+                style:Style{
+                    margin:UiRect::top(Val::Px(6.)),
+                    padding:UiRect::all(Val::Px(4.)),
+                    justify_content:JustifyContent::Center,
+                    ..default()
+                },
+                background_color:Color::rgb(0.25,0.25,0.25).into(),
+                ..default()
+            })
+            .insert(action)
+            .with_children(|cb|{
+                cb.spawn(TextBundle::from_section(label, style.clone()));
+            });
+        }
+    });
+}
+
+fn create_info_on_selected(
+    mut commands:Commands,
+    assets:Res<GameAssets>,
+    editor:Res<LevelEditor>,
+    selection:Query<&Selection,With<TowerSite>>,
+    root:Query<Entity,With<TowerInfoUI>>
+){
+    let any_selection = !editor.enabled && selection.iter().any(|s|{s.selected()});
+    match root.get_single() {
+        Ok(e) => {
+            if !any_selection {
+                commands.entity(e).despawn_recursive();
+            }
+        },
+        Err(QuerySingleError::NoEntities(..)) => {
+            if any_selection {
+                create_info_ui(commands, assets);
+            }
+        },
+        Err(..) => {
+            error!("too many tower info roots!")
+        }
+    }
+}
+
+fn update_tower_info(
+    sites:Query<(&Selection,&Children),With<TowerSite>>,
+    towers:Query<(&Tower,&TowerType,&Health)>,
+    tuning:Res<Tuning>,
+    mut texts:Query<&mut Text,With<TowerInfoText>>
+){
+    let selected = sites.iter()
+    .filter(|(selection,_)| selection.selected())
+    .flat_map(|(_,children)| children.iter())
+    .find_map(|child| towers.get(*child).ok());
+    let (tower,tower_type,health) = match selected {
+        Some(selected) => selected,
+        None => return
+    };
+
+    let upgrade = if tower.can_upgrade() {
+        format!("Upgrade: {} gold",tower.upgrade_cost(tower_type,&tuning))
+    } else {
+        "Max tier".to_string()
+    };
+    for mut text in texts.iter_mut() {
+        text.sections[0].value = format!(
+            "{:?}  tier {}/{}\nHealth: {}/{}\nDamage: {}\nFire rate: {:.2}/s\nRange: {:.1}\nHits: {:?}\nTargeting: {:?}\nKills: {}\nDamage dealt: {}\n{}\nSell: {} gold\nRepair: {} gold",
+            tower_type,tower.tier,MAX_TIER,health.value.max(0),health.max,
+            tower.damage,tower.fire_rate(),tower.range,tower.targets,tower.targeting,
+            tower.kills,tower.damage_dealt,
+            upgrade,tower.sell_price(),repair_cost(health)
+        );
+    }
+}
+
+fn tower_action_clicked(
+    mut commands:Commands,
+    mut materials:ResMut<Assets<StandardMaterial>>,
+    mut stats:ResMut<PlayerStats>,
+    mut towers:Query<(&mut Tower,&TowerType,&mut Health)>,
+    sites:Query<(Entity,&Selection,&Children,&Transform,&TowerSite)>,
+    assets:Res<GameAssets>,
+    tuning:Res<Tuning>,
+    query:Query<(&Interaction,&TowerAction),Changed<Interaction>>,
+    input:Query<&ActionState<Action>,With<PlayerInput>>,
+    mut upgraded:EventWriter<TowerUpgraded>,
+    mut sold:EventWriter<TowerSold>,
+    mut repaired:EventWriter<TowerRepaired>
+){
+    let clicked = query.iter()
+    .filter(|(interaction,_)| matches!(interaction,Interaction::Clicked))
+    .map(|(_,action)| *action);
+    //快捷键和点按钮效果一样
+    let hotkeys = [
+        (Action::Upgrade,TowerAction::Upgrade),
+        (Action::Sell,TowerAction::Sell),
+        (Action::CycleTargeting,TowerAction::Priority),
+        (Action::Repair,TowerAction::Repair)
+    ];
+    let pressed = input.iter().flat_map(|input|{
+        hotkeys.iter().filter(|(key,_)| input.just_pressed(*key)).map(|(_,action)| *action)
+    });
+    //一帧只处理一个操作,点按钮和按快捷键同时发生时不会重复卖塔或升级
+    let action = match clicked.chain(pressed).next() {
+        Some(action) => action,
+        None => return
+    };
+    for (e,selection,children,transform,site) in sites.iter() {
+        if !selection.selected() {
+            continue;
+        }
+        let child = match children.iter().find(|c| towers.contains(**c)) {
+            Some(child) => *child,
+            None => continue
+        };
+        let (mut tower,tower_type,mut health) = towers.get_mut(child).unwrap();
+        match action {
+            TowerAction::Upgrade => {
+                let cost = tower.upgrade_cost(tower_type,&tuning);
+                if !tower.can_upgrade() {
+                    info!("tower is max tier!");
+                } else if stats.gold < cost {
+                    info!("not enough gold!");
+                } else {
+                    stats.gold -= cost;
+                    tower.upgrade(cost);
+                    upgraded.send(TowerUpgraded{ tower: child, tower_type: *tower_type, tier: tower.tier, cost });
+                }
+            },
+            TowerAction::Sell => {
+                let refund = tower.sell_price();
+                stats.gold += refund;
+                commands.entity(e).despawn_recursive();
+                if let Some(rotation) = site.slot {
+                    spawn_tower_slot(&mut commands, &mut materials, &assets, transform.translation, rotation);
+                }
+                sold.send(TowerSold{ site: e, tower_type: *tower_type, position: transform.translation, refund });
+            },
+            TowerAction::Priority => {
+                tower.targeting = tower.targeting.next();
+            },
+            TowerAction::Repair => {
+                let cost = repair_cost(&health);
+                if health.value >= health.max {
+                    info!("tower is not damaged!");
+                } else if stats.gold < cost {
+                    info!("not enough gold!");
+                } else {
+                    stats.gold -= cost;
+                    health.value = health.max;
+                    repaired.send(TowerRepaired{ tower: child, tower_type: *tower_type, cost });
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use crate::events::*;
+use crate::level::*;
+use crate::loading::*;
+use crate::target::*;
+use crate::tuning::*;
+
+#[derive(Resource,Default)]
+pub struct WaveState{
+    pub waves:Vec<WaveDesc>,
+    //已经出动的波数
+    pub current:usize,
+    pub timer:Timer,
+    //已出动但还没被消灭或漏掉的敌人,包括还没出生的
+    pub remaining:u32,
+    //这一波还在出怪
+    pub spawning:bool,
+    //场上活着的首领,打掉之后下一波才开始倒计时
+    pub bosses:u32,
+}
+
+impl WaveState {
+    pub fn new(waves:Vec<WaveDesc>) -> Self {
+        let delay = waves.first().map(|w| w.delay).unwrap_or_default();
+        WaveState{
+            waves,
+            current:0,
+            timer:Timer::from_seconds(delay, TimerMode::Once),
+            remaining:0,
+            spawning:false,
+            bosses:0
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.current >= self.waves.len()
+    }
+
+    //敌人被消灭或到达终点
+    pub fn enemy_gone(&mut self) {
+        self.remaining = self.remaining.saturating_sub(1);
+    }
+}
+
+pub struct WavePlugin;
+
+impl Plugin for WavePlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_resource::<WaveState>()
+        .add_system_set(
+            SystemSet::on_update(GameState::Playing)
+            .with_system(wave_launch)
+            .with_system(wave_track_enemies)
+        );
+    }
+}
+
+fn wave_launch(
+    mut waves:ResMut<WaveState>,
+    mut factories:Query<&mut TargetFactory>,
+    tuning:Res<Tuning>,
+    time:Res<Time>
+){
+    //上一波还没出完
+    let spawning = factories.iter().any(|f| !f.pending.is_empty());
+    if waves.spawning != spawning {
+        waves.spawning = spawning;
+    }
+    if waves.finished() || spawning || waves.bosses > 0 {
+        return;
+    }
+
+    //等待时间乘上数值配置里的比例,改配置后马上生效
+    let delay = waves.waves[waves.current].delay * tuning.waves.delay_scale;
+    if waves.timer.duration().as_secs_f32() != delay {
+        waves.timer.set_duration(Duration::from_secs_f32(delay.max(0.)));
+    }
+    waves.timer.tick(time.delta());
+    if !waves.timer.finished() {
+        return;
+    }
+
+    let wave = waves.waves[waves.current].clone();
+    for group in wave.groups.iter() {
+        for mut factory in factories.iter_mut() {
+            if factory.route == group.path {
+                factory.spawn_timer = Timer::from_seconds(group.interval * tuning.waves.interval_scale, TimerMode::Repeating);
+                factory.pending.extend(std::iter::repeat(group.enemy).take(group.count as usize));
+                waves.remaining += group.count;
+            }
+        }
+    }
+
+    waves.current += 1;
+    info!("wave {} start!",waves.current);
+    let next_delay = waves.waves.get(waves.current).map(|w| w.delay);
+    if let Some(delay) = next_delay {
+        waves.timer = Timer::from_seconds(delay, TimerMode::Once);
+    }
+}
+
+//敌人被消灭,漏掉或撞塔后这一波剩余数减一
+fn wave_track_enemies(
+    mut waves:ResMut<WaveState>,
+    mut killed:EventReader<EnemyKilled>,
+    mut leaked:EventReader<EnemyLeaked>,
+    mut rammed:EventReader<EnemyRammed>
+){
+    let gone = killed.iter().count() + leaked.iter().count() + rammed.iter().count();
+    for _ in 0..gone {
+        waves.enemy_gone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn killed_leaked_and_rammed_enemies_leave_the_wave() {
+        let mut app = App::new();
+        app
+        .insert_resource(WaveState{ remaining: 5, ..WaveState::new(Vec::new()) })
+        .add_event::<EnemyKilled>()
+        .add_event::<EnemyLeaked>()
+        .add_event::<EnemyRammed>()
+        .add_system(wave_track_enemies);
+
+        let enemy = app.world.spawn_empty().id();
+        let tower = app.world.spawn_empty().id();
+        app.world.send_event(EnemyKilled{ enemy, enemy_type: EnemyType::Red, position: Vec3::ZERO });
+        app.world.send_event(EnemyLeaked{ enemy, enemy_type: EnemyType::Red });
+        app.world.send_event(EnemyRammed{ enemy, enemy_type: EnemyType::Crawler, tower });
+        app.update();
+        assert_eq!(app.world.resource::<WaveState>().remaining, 2);
+
+        //不会减到负数
+        for _ in 0..3 {
+            app.world.send_event(EnemyLeaked{ enemy, enemy_type: EnemyType::Red });
+        }
+        app.update();
+        assert_eq!(app.world.resource::<WaveState>().remaining, 0);
+    }
+}